@@ -0,0 +1,186 @@
+use crate::state::{ExecTrace, Flow, FlowThread, ThreadId};
+use crate::util_types::CallStackTreeNode;
+use json::JsonValue;
+use std::sync::{Arc, Mutex};
+
+// Exports flows in the Chrome Trace Event format, so they can be opened in Perfetto
+// or chrome://tracing. Every fn call becomes a complete ("X") event on its thread track,
+// and every expression that threw becomes an instant ("i") event.
+
+// Our timestamps are in milliseconds, trace events in microseconds
+fn micros(millis: u64) -> u64 {
+    millis * 1000
+}
+
+fn call_events(
+    flow_id: i64,
+    thread_id: ThreadId,
+    traces: &[ExecTrace],
+    node_pointer: &Arc<Mutex<CallStackTreeNode>>,
+    end_of_time: u64,
+    events: &mut Vec<JsonValue>,
+) {
+    let node = node_pointer.lock().unwrap();
+    let fct = match &traces[node.trace_idx] {
+        ExecTrace::FnCallTrace(fct) => fct,
+        ExecTrace::ExprTrace(_) => panic!("call_stack_tree is pointing to a non FnCallTrace"),
+    };
+
+    let mut args = json::object! { "args" => fct.args_vec.as_str() };
+    // calls that never returned last until the end of the recording
+    let mut ret_timestamp = end_of_time;
+    if let Some(ExecTrace::ExprTrace(ret)) = node.ret_trace_idx.map(|idx| &traces[idx]) {
+        ret_timestamp = ret.timestamp;
+        match &ret.err {
+            Some(err) => args["error"] = err.as_str().into(),
+            None => args["return"] = ret.result.as_str().into(),
+        }
+    }
+
+    events.push(json::object! {
+        "name" => format!("{}/{}", fct.fn_ns, fct.fn_name),
+        "cat" => fct.fn_ns.as_str(),
+        "ph" => "X",
+        "ts" => micros(fct.timestamp),
+        "dur" => micros(ret_timestamp.saturating_sub(fct.timestamp)),
+        "pid" => flow_id,
+        "tid" => thread_id,
+        "args" => args,
+    });
+
+    for child in &node.childs {
+        call_events(flow_id, thread_id, traces, child, end_of_time, events);
+    }
+}
+
+fn thread_events(flow_id: i64, thread: &FlowThread, events: &mut Vec<JsonValue>) {
+    events.push(json::object! {
+        "name" => "thread_name",
+        "ph" => "M",
+        "pid" => flow_id,
+        "tid" => thread.thread_id,
+        "args" => json::object! { "name" => format!("thread-{}", thread.thread_id) },
+    });
+
+    let traces = &thread.execution.traces;
+    if let Some(cst) = &thread.call_stack_tree {
+        let end_of_time = traces.iter().map(ExecTrace::timestamp).max().unwrap_or(0);
+        call_events(
+            flow_id,
+            thread.thread_id,
+            traces,
+            &cst.root,
+            end_of_time,
+            events,
+        );
+    }
+
+    for t in traces {
+        if let ExecTrace::ExprTrace(et) = t {
+            if let Some(err) = &et.err {
+                events.push(json::object! {
+                    "name" => "Exception",
+                    "cat" => "exception",
+                    "ph" => "i",
+                    "s" => "t",
+                    "ts" => micros(et.timestamp),
+                    "pid" => flow_id,
+                    "tid" => thread.thread_id,
+                    "args" => json::object! {
+                        "error" => err.as_str(),
+                        "form-id" => et.form_id,
+                        "coor" => format!("{:?}", et.coord),
+                    },
+                });
+            }
+        }
+    }
+}
+
+pub fn flow_chrome_trace(flow: &Flow) -> JsonValue {
+    let mut events = vec![json::object! {
+        "name" => "process_name",
+        "ph" => "M",
+        "pid" => flow.flow_id,
+        "args" => json::object! { "name" => format!("Flow-{}", flow.flow_id) },
+    }];
+
+    let mut thread_ids = flow.thread_ids();
+    thread_ids.sort_unstable();
+    for thread_id in thread_ids {
+        thread_events(flow.flow_id, &flow.threads[&thread_id], &mut events);
+    }
+
+    json::object! {
+        "traceEvents" => JsonValue::Array(events),
+        "displayTimeUnit" => "ms",
+    }
+}
+
+pub fn export_flow_chrome_trace(flow: &Flow, file_path: &str) -> Result<(), String> {
+    std::fs::write(file_path, json::stringify(flow_chrome_trace(flow)))
+        .map_err(|e| format!("Couldn't write {}: {}", file_path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{DebuggerState, ExprTrace, FnCallTrace, Form};
+
+    #[test]
+    fn flow_chrome_trace_test() {
+        let mut state = DebuggerState::new();
+        state.add_flow_form(
+            1,
+            1,
+            Form::new(1, "user".to_string(), "(defn f [])".to_string(), 0),
+            0,
+        );
+        let call = |fn_name: &str, timestamp| {
+            FnCallTrace::new(
+                1,
+                "user".to_string(),
+                fn_name.to_string(),
+                "[]".to_string(),
+                timestamp,
+            )
+        };
+
+        // (f (g) (h <throws>))
+        state.add_fn_call_trace(1, 2, call("f", 1));
+        state.add_fn_call_trace(1, 2, call("g", 2));
+        state.add_exec_trace(1, 2, ExprTrace::new(1, "4".to_string(), vec![], true, 3));
+        state.add_fn_call_trace(1, 2, call("h", 5));
+        state.add_exec_trace(
+            1,
+            2,
+            ExprTrace::new(1, String::new(), vec![], true, 7).with_err(Some("Boom".to_string())),
+        );
+
+        let trace = flow_chrome_trace(&state.flows[&1]);
+        let events = &trace["traceEvents"];
+        let phases = events
+            .members()
+            .map(|e| format!("{} {}", e["ph"], e["name"]))
+            .collect::<Vec<String>>();
+
+        assert_eq!(
+            phases,
+            vec![
+                "M process_name",
+                "M thread_name",
+                "X user/f",
+                "X user/g",
+                "X user/h",
+                "i Exception",
+            ]
+        );
+        // f never returned, so it lasts until the last trace
+        assert_eq!(events[2]["dur"], 6000);
+        assert_eq!(events[3]["ts"], 2000);
+        assert_eq!(events[3]["dur"], 1000);
+        assert_eq!(events[3]["args"]["return"], "4");
+        assert_eq!(events[4]["args"]["error"], "Boom");
+        assert_eq!(events[5]["ts"], 7000);
+    }
+}
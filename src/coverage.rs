@@ -0,0 +1,562 @@
+use crate::lisp_reader::PrintableLispForm;
+use crate::state::{Coord, Flow, Form, FormId};
+use json::JsonValue;
+use std::collections::{BTreeMap, HashSet};
+
+// Code coverage computed from the hot coords of a flow.
+//
+// Instrumentable coords are the ones the tracer can report: every list (fn calls, macros
+// and special forms) and every symbol that isn't in a call head position. Literals,
+// quoted forms, and binding places (let vectors names, fn names and params) are never
+// traced, so they aren't counted.
+//
+// For if/when/cond/case/condp we also report which branches never ran. A branch is taken
+// when any of its instrumentable coords is hot, branches made only of literals can't be
+// observed so they are skipped.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub coord: Coord,
+    pub kind: String,
+    pub label: String,
+    pub covered: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormCoverage {
+    pub form_id: FormId,
+    pub ns: String,
+    pub executed: usize,
+    pub total: usize,
+    pub branches: Vec<Branch>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NsCoverage {
+    pub ns: String,
+    pub forms: Vec<FormCoverage>,
+}
+
+const BINDING_SYMBS: &[&str] = &[
+    "let",
+    "let*",
+    "loop",
+    "loop*",
+    "binding",
+    "with-open",
+    "with-redefs",
+    "when-let",
+    "if-let",
+    "when-some",
+    "if-some",
+    "when-first",
+    "for",
+    "doseq",
+    "dotimes",
+];
+
+const FN_SYMBS: &[&str] = &["defn", "defn-", "defmacro", "fn", "fn*"];
+
+fn child_coord(coord: &[u16], idx: usize) -> Coord {
+    let mut c = coord.to_vec();
+    c.push(idx as u16);
+    c
+}
+
+fn head_symbol(childs: &[PrintableLispForm]) -> Option<&str> {
+    match childs.first() {
+        Some(PrintableLispForm::Atomic(atom, _)) if atom.is_symbol() => Some(atom.text.as_str()),
+        _ => None,
+    }
+}
+
+fn collect_childs(childs: &[PrintableLispForm], coords: &mut Vec<Coord>) {
+    for c in childs {
+        collect_instrumentable(c, coords);
+    }
+}
+
+fn collect_fn_body(childs: &[PrintableLispForm], coords: &mut Vec<Coord>) {
+    for (i, c) in childs.iter().enumerate().skip(1) {
+        match c {
+            // fn name
+            PrintableLispForm::Atomic(_, _) if i == 1 => (),
+            // params vector
+            PrintableLispForm::Vector { .. } => (),
+            // an arity, ([params] body)
+            PrintableLispForm::List {
+                childs: arity_childs,
+                ..
+            } if matches!(arity_childs.first(), Some(PrintableLispForm::Vector { .. })) => {
+                collect_childs(&arity_childs[1..], coords)
+            }
+            _ => collect_instrumentable(c, coords),
+        }
+    }
+}
+
+fn collect_instrumentable(pform: &PrintableLispForm, coords: &mut Vec<Coord>) {
+    match pform {
+        PrintableLispForm::Atomic(atom, coord) => {
+            if atom.is_symbol() {
+                coords.push(coord.clone());
+            }
+        }
+        PrintableLispForm::List { childs, coord, .. } => {
+            if childs.is_empty() {
+                return;
+            }
+            let head = head_symbol(childs);
+            if head == Some("quote") {
+                return;
+            }
+            coords.push(coord.clone());
+
+            if head.is_some_and(|h| FN_SYMBS.contains(&h)) {
+                collect_fn_body(childs, coords);
+            } else if head.is_some_and(|h| BINDING_SYMBS.contains(&h)) {
+                if let Some(PrintableLispForm::Vector {
+                    childs: bindings, ..
+                }) = childs.get(1)
+                {
+                    // only the bound expressions, not the binding places
+                    for expr in bindings.iter().skip(1).step_by(2) {
+                        collect_instrumentable(expr, coords);
+                    }
+                }
+                collect_childs(&childs[usize::min(2, childs.len())..], coords);
+            } else {
+                // a symbol in the head position is what we are calling, not a value
+                let skip = if head.is_some() { 1 } else { 0 };
+                collect_childs(&childs[skip..], coords);
+            }
+        }
+        PrintableLispForm::Vector { childs, .. } | PrintableLispForm::Set { childs, .. } => {
+            collect_childs(childs, coords)
+        }
+        PrintableLispForm::Map { keys, vals, .. } => {
+            collect_childs(keys, coords);
+            collect_childs(vals, coords);
+        }
+        PrintableLispForm::Tagged { form, .. } => collect_instrumentable(form, coords),
+        PrintableLispForm::ReaderMacro {
+            prefix,
+            form,
+            coord,
+        } => {
+            // only @x is a call, quoted forms and var names aren't evaluated
+            if prefix == "@" {
+                coords.push(coord.clone());
+                collect_instrumentable(form, coords);
+            }
+        }
+        PrintableLispForm::AnonFn { body, coord } => {
+            coords.push(coord.clone());
+            collect_instrumentable(body, coords);
+        }
+        PrintableLispForm::Meta { form, .. } => collect_instrumentable(form, coords),
+        PrintableLispForm::ReaderConditional {
+            splicing,
+            vals,
+            selected,
+            ..
+        } => collect_childs(compiled_forms(vals, *selected, *splicing), coords),
+        PrintableLispForm::NamespacedMap { map, .. } => collect_instrumentable(map, coords),
+        PrintableLispForm::String(_)
+        | PrintableLispForm::Regexp(_)
+        | PrintableLispForm::Truncated(_)
+        | PrintableLispForm::TruncatedString { .. } => (),
+    }
+}
+
+// The reader conditional forms the tracer sees, with spliced ones unwrapped
+fn compiled_forms(
+    vals: &[PrintableLispForm],
+    selected: Option<usize>,
+    splicing: bool,
+) -> &[PrintableLispForm] {
+    match selected.map(|i| &vals[i]) {
+        Some(PrintableLispForm::Vector { childs, .. })
+        | Some(PrintableLispForm::List { childs, .. })
+            if splicing =>
+        {
+            childs
+        }
+        Some(val) => std::slice::from_ref(val),
+        None => &[],
+    }
+}
+
+pub fn instrumentable_coords(pform: &PrintableLispForm) -> Vec<Coord> {
+    let mut coords = Vec::new();
+    collect_instrumentable(pform, &mut coords);
+    coords
+}
+
+fn short_label(pform: &PrintableLispForm) -> String {
+    let s = pform.to_string();
+    match s.char_indices().nth(40) {
+        Some((i, _)) => format!("{}...", &s[0..i]),
+        None => s,
+    }
+}
+
+fn push_branch(
+    kind: &str,
+    label: String,
+    coord: &[u16],
+    branch_forms: &[PrintableLispForm],
+    hot: &HashSet<Coord>,
+    branches: &mut Vec<Branch>,
+) {
+    let mut coords = Vec::new();
+    collect_childs(branch_forms, &mut coords);
+    if !coords.is_empty() {
+        branches.push(Branch {
+            coord: coord.to_vec(),
+            kind: kind.to_string(),
+            label,
+            covered: coords.iter().any(|c| hot.contains(c)),
+        });
+    }
+}
+
+// pairs of (test, expr) starting at from, with an optional trailing default
+fn pairs_branches(
+    kind: &str,
+    childs: &[PrintableLispForm],
+    from: usize,
+    hot: &HashSet<Coord>,
+    branches: &mut Vec<Branch>,
+) {
+    let mut i = from;
+    while i < childs.len() {
+        if i + 1 < childs.len() {
+            push_branch(
+                kind,
+                short_label(&childs[i]),
+                &child_coord(&[], i),
+                &childs[i + 1..i + 2],
+                hot,
+                branches,
+            );
+        } else {
+            push_branch(
+                kind,
+                "default".to_string(),
+                &child_coord(&[], i),
+                &childs[i..],
+                hot,
+                branches,
+            );
+        }
+        i += 2;
+    }
+}
+
+fn collect_branches(pform: &PrintableLispForm, hot: &HashSet<Coord>, branches: &mut Vec<Branch>) {
+    match pform {
+        PrintableLispForm::List { childs, coord, .. } => {
+            let first_branch = branches.len();
+            match head_symbol(childs) {
+                Some("quote") => return,
+                Some(h @ "if") | Some(h @ "if-not") | Some(h @ "if-let") | Some(h @ "if-some") => {
+                    let then_form = childs.get(2..3).unwrap_or_default();
+                    let else_form = childs.get(3..4).unwrap_or_default();
+                    push_branch(h, "then".to_string(), &[2], then_form, hot, branches);
+                    push_branch(h, "else".to_string(), &[3], else_form, hot, branches);
+                }
+                Some(h @ "when")
+                | Some(h @ "when-not")
+                | Some(h @ "when-let")
+                | Some(h @ "when-some") => {
+                    let body = childs.get(2..).unwrap_or_default();
+                    push_branch(h, "body".to_string(), &[2], body, hot, branches);
+                }
+                Some("cond") => pairs_branches("cond", childs, 1, hot, branches),
+                Some("case") => pairs_branches("case", childs, 2, hot, branches),
+                Some("condp") => pairs_branches("condp", childs, 3, hot, branches),
+                _ => (),
+            }
+            // branch coords were relative to the list, make them absolute
+            for b in &mut branches[first_branch..] {
+                let mut abs = coord.clone();
+                abs.append(&mut b.coord);
+                b.coord = abs;
+            }
+            for c in childs {
+                collect_branches(c, hot, branches);
+            }
+        }
+        PrintableLispForm::Vector { childs, .. } | PrintableLispForm::Set { childs, .. } => {
+            for c in childs {
+                collect_branches(c, hot, branches);
+            }
+        }
+        PrintableLispForm::Map { keys, vals, .. } => {
+            for c in keys.iter().chain(vals) {
+                collect_branches(c, hot, branches);
+            }
+        }
+        PrintableLispForm::Tagged { form, .. } | PrintableLispForm::Meta { form, .. } => {
+            collect_branches(form, hot, branches)
+        }
+        PrintableLispForm::ReaderMacro { prefix, form, .. } => {
+            if prefix == "@" {
+                collect_branches(form, hot, branches)
+            }
+        }
+        PrintableLispForm::AnonFn { body, .. } => collect_branches(body, hot, branches),
+        PrintableLispForm::ReaderConditional {
+            splicing,
+            vals,
+            selected,
+            ..
+        } => {
+            for c in compiled_forms(vals, *selected, *splicing) {
+                collect_branches(c, hot, branches);
+            }
+        }
+        PrintableLispForm::NamespacedMap { map, .. } => collect_branches(map, hot, branches),
+        PrintableLispForm::Atomic(_, _)
+        | PrintableLispForm::String(_)
+        | PrintableLispForm::Regexp(_)
+        | PrintableLispForm::Truncated(_)
+        | PrintableLispForm::TruncatedString { .. } => (),
+    }
+}
+
+// Forms we couldn't read have nothing instrumentable
+pub fn form_coverage(form: &Form, hot: &HashSet<Coord>) -> FormCoverage {
+    let coords = form
+        .lisp_form
+        .as_ref()
+        .map_or_else(|_| Vec::new(), instrumentable_coords);
+    let mut branches = Vec::new();
+    if let Ok(lisp_form) = &form.lisp_form {
+        collect_branches(lisp_form, hot, &mut branches);
+    }
+
+    FormCoverage {
+        form_id: form.form_id,
+        ns: form.ns.clone(),
+        executed: coords.iter().filter(|c| hot.contains(*c)).count(),
+        total: coords.len(),
+        branches,
+    }
+}
+
+impl NsCoverage {
+    pub fn executed(&self) -> usize {
+        self.forms.iter().map(|f| f.executed).sum()
+    }
+
+    pub fn total(&self) -> usize {
+        self.forms.iter().map(|f| f.total).sum()
+    }
+}
+
+pub fn percentage(executed: usize, total: usize) -> f32 {
+    if total == 0 {
+        100.0
+    } else {
+        executed as f32 * 100.0 / total as f32
+    }
+}
+
+// Coverage of all the flow forms, by namespace, merging the hot coords of every thread
+pub fn flow_coverage(flow: &Flow) -> Vec<NsCoverage> {
+    let mut by_ns: BTreeMap<String, Vec<FormCoverage>> = BTreeMap::new();
+    let empty = HashSet::new();
+
+    for form in flow.forms.iter() {
+        let mut hot: HashSet<Coord> = HashSet::new();
+        for thread in flow.threads.values() {
+            if let Some(form_hot) = thread.hot_coords.get(&form.form_id) {
+                hot.extend(form_hot.keys().cloned());
+            }
+        }
+        let fc = form_coverage(form, if hot.is_empty() { &empty } else { &hot });
+        by_ns.entry(fc.ns.clone()).or_default().push(fc);
+    }
+
+    by_ns
+        .into_iter()
+        .map(|(ns, forms)| NsCoverage { ns, forms })
+        .collect()
+}
+
+pub fn coverage_json(coverage: &[NsCoverage]) -> JsonValue {
+    let namespaces = coverage
+        .iter()
+        .map(|nc| {
+            let forms = nc
+                .forms
+                .iter()
+                .map(|fc| {
+                    let uncovered = fc
+                        .branches
+                        .iter()
+                        .filter(|b| !b.covered)
+                        .map(|b| {
+                            json::object! {
+                                "coor" => JsonValue::Array(b.coord.iter().map(|c| (*c).into()).collect()),
+                                "kind" => b.kind.as_str(),
+                                "label" => b.label.as_str(),
+                            }
+                        })
+                        .collect();
+                    json::object! {
+                        "form-id" => fc.form_id,
+                        "executed" => fc.executed,
+                        "total" => fc.total,
+                        "branches" => fc.branches.len(),
+                        "uncovered-branches" => JsonValue::Array(uncovered),
+                    }
+                })
+                .collect();
+            json::object! {
+                "ns" => nc.ns.as_str(),
+                "executed" => nc.executed(),
+                "total" => nc.total(),
+                "forms" => JsonValue::Array(forms),
+            }
+        })
+        .collect();
+
+    json::object! {
+        "executed" => coverage.iter().map(|nc| nc.executed()).sum::<usize>(),
+        "total" => coverage.iter().map(|nc| nc.total()).sum::<usize>(),
+        "namespaces" => JsonValue::Array(namespaces),
+    }
+}
+
+pub fn coverage_text(coverage: &[NsCoverage]) -> String {
+    let mut r = String::new();
+    let (mut executed, mut total) = (0, 0);
+
+    for nc in coverage {
+        executed += nc.executed();
+        total += nc.total();
+        r.push_str(&format!(
+            "{:<50} {:>6}/{:<6} {:>6.2}%\n",
+            nc.ns,
+            nc.executed(),
+            nc.total(),
+            percentage(nc.executed(), nc.total())
+        ));
+        for fc in &nc.forms {
+            for b in fc.branches.iter().filter(|b| !b.covered) {
+                r.push_str(&format!(
+                    "    form {} never took {} branch {} at {:?}\n",
+                    fc.form_id, b.kind, b.label, b.coord
+                ));
+            }
+        }
+    }
+    r.push_str(&format!(
+        "{:<50} {:>6}/{:<6} {:>6.2}%\n",
+        "TOTAL",
+        executed,
+        total,
+        percentage(executed, total)
+    ));
+    r
+}
+
+// Writes the flow coverage as `{file_prefix}.json` and `{file_prefix}.txt`
+pub fn export_flow_coverage(flow: &Flow, file_prefix: &str) -> Result<String, String> {
+    let coverage = flow_coverage(flow);
+    let json_path = format!("{}.json", file_prefix);
+    let txt_path = format!("{}.txt", file_prefix);
+    std::fs::write(
+        &json_path,
+        json::stringify_pretty(coverage_json(&coverage), 2),
+    )
+    .map_err(|e| format!("Couldn't write {}: {}", json_path, e))?;
+    std::fs::write(&txt_path, coverage_text(&coverage))
+        .map_err(|e| format!("Couldn't write {}: {}", txt_path, e))?;
+    Ok(format!("Exported to {} and {}", json_path, txt_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn form_coverage_test() {
+        let form = Form::new(
+            1,
+            "user".to_string(),
+            "(defn factorial [n] (if (zero? n) 1 (* n (factorial (dec n)))))".to_string(),
+            0,
+        );
+
+        let mut coords = instrumentable_coords(form.lisp_form.as_ref().unwrap());
+        coords.sort();
+        assert_eq!(
+            coords,
+            vec![
+                vec![],
+                vec![3],
+                vec![3, 1],
+                vec![3, 1, 1],
+                vec![3, 3],
+                vec![3, 3, 1],
+                vec![3, 3, 2],
+                vec![3, 3, 2, 1],
+                vec![3, 3, 2, 1, 1],
+            ]
+        );
+
+        // only called with (factorial 0)
+        let hot: HashSet<Coord> = vec![vec![], vec![3], vec![3, 1], vec![3, 1, 1]]
+            .into_iter()
+            .collect();
+        let fc = form_coverage(&form, &hot);
+
+        assert_eq!((fc.executed, fc.total), (4, 9));
+        // the then branch is a literal, so only the else one can be observed
+        assert_eq!(
+            fc.branches,
+            vec![Branch {
+                coord: vec![3, 3],
+                kind: "if".to_string(),
+                label: "else".to_string(),
+                covered: false
+            }]
+        );
+    }
+
+    #[test]
+    fn binding_coverage_test() {
+        let form = Form::new(
+            1,
+            "user".to_string(),
+            "(let [a (inc x) b 2] (cond (pos? a) :pos :else (str b)))".to_string(),
+            0,
+        );
+
+        let mut coords = instrumentable_coords(form.lisp_form.as_ref().unwrap());
+        coords.sort();
+        assert_eq!(
+            coords,
+            vec![
+                vec![],
+                vec![1, 1],
+                vec![1, 1, 1],
+                vec![2],
+                vec![2, 1],
+                vec![2, 1, 1],
+                vec![2, 4],
+                vec![2, 4, 1],
+            ]
+        );
+
+        let hot: HashSet<Coord> = vec![vec![2, 4, 1]].into_iter().collect();
+        let fc = form_coverage(&form, &hot);
+        assert_eq!(fc.branches.len(), 1);
+        assert_eq!(fc.branches[0].label, ":else");
+        assert!(fc.branches[0].covered);
+    }
+}
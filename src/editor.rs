@@ -0,0 +1,151 @@
+use crate::state::SourceLocation;
+use std::process::Command;
+use std::sync::OnceLock;
+
+// Opening forms in the user editor, with a command template like
+//
+//   emacsclient -n +{line}:{col} {file}
+//   code -g {file}:{line}:{col}
+//   idea --line {line} {file}
+//
+// taken from the FLOWSTORM_EDITOR environment variable.
+
+pub const EDITOR_ENV_VAR: &str = "FLOWSTORM_EDITOR";
+
+pub trait EditorLauncher {
+    // lines and columns start at 1
+    fn open(&self, file: &str, line: usize, column: usize) -> Result<(), String>;
+}
+
+#[derive(Debug, Clone)]
+pub struct CommandLauncher {
+    template: String,
+}
+
+impl CommandLauncher {
+    pub fn new(template: String) -> Result<Self, String> {
+        if !template.contains("{file}") {
+            return Err(format!(
+                "The editor command {} should have a {{file}} in it",
+                template
+            ));
+        }
+        Ok(CommandLauncher { template })
+    }
+
+    // The template is split on whitespace before filling it in, so files with
+    // spaces stay in one arg
+    pub fn command_args(&self, file: &str, line: usize, column: usize) -> Vec<String> {
+        self.template
+            .split_whitespace()
+            .map(|arg| {
+                arg.replace("{file}", file)
+                    .replace("{line}", &line.to_string())
+                    .replace("{col}", &column.to_string())
+            })
+            .collect()
+    }
+}
+
+impl EditorLauncher for CommandLauncher {
+    fn open(&self, file: &str, line: usize, column: usize) -> Result<(), String> {
+        let args = self.command_args(file, line, column);
+        // we don't wait for it, editors like idea don't return until they are closed
+        Command::new(&args[0])
+            .args(&args[1..])
+            .spawn()
+            .map(|_| ())
+            .map_err(|e| format!("Couldn't run {}: {}", args.join(" "), e))
+    }
+}
+
+pub fn open_location(
+    launcher: &dyn EditorLauncher,
+    location: &SourceLocation,
+) -> Result<(), String> {
+    match &location.file {
+        Some(file) => launcher.open(file, location.line, location.column.unwrap_or(1)),
+        None => Err("The tracer didn't send the file of this form".to_string()),
+    }
+}
+
+static EDITOR_LAUNCHER: OnceLock<Box<dyn EditorLauncher + Send + Sync>> = OnceLock::new();
+
+// Only the first call wins, it is meant to be called once at startup
+pub fn set_editor_launcher(launcher: Box<dyn EditorLauncher + Send + Sync>) {
+    let _ = EDITOR_LAUNCHER.set(launcher);
+}
+
+// None until set_editor_launcher gets called
+pub fn editor_launcher() -> Option<&'static (dyn EditorLauncher + Send + Sync)> {
+    EDITOR_LAUNCHER.get().map(|l| l.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Form;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct RecordingLauncher {
+        opened: RefCell<Vec<(String, usize, usize)>>,
+    }
+
+    impl EditorLauncher for RecordingLauncher {
+        fn open(&self, file: &str, line: usize, column: usize) -> Result<(), String> {
+            self.opened
+                .borrow_mut()
+                .push((file.to_string(), line, column));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn command_args_test() {
+        let emacs =
+            CommandLauncher::new("emacsclient -n +{line}:{col} {file}".to_string()).unwrap();
+        assert_eq!(
+            emacs.command_args("src/my app/core.clj", 12, 3),
+            vec!["emacsclient", "-n", "+12:3", "src/my app/core.clj"]
+        );
+        let code = CommandLauncher::new("code -g {file}:{line}:{col}".to_string()).unwrap();
+        assert_eq!(
+            code.command_args("core.clj", 1, 1),
+            vec!["code", "-g", "core.clj:1:1"]
+        );
+        assert!(CommandLauncher::new("idea --line {line}".to_string()).is_err());
+    }
+
+    #[test]
+    fn open_coord_location_test() {
+        let launcher = RecordingLauncher::default();
+        let form = Form::new(1, "user".to_string(), "(defn f [a] (inc a))".to_string(), 0)
+            .with_source("(defn f [a]\n  (inc a))".to_string())
+            .with_location(SourceLocation {
+                file: Some("src/user.clj".to_string()),
+                line: 10,
+                column: Some(3),
+            });
+
+        for coord in [vec![], vec![2], vec![3, 1]] {
+            open_location(&launcher, &form.coord_location(&coord).unwrap()).unwrap();
+        }
+        assert_eq!(
+            *launcher.opened.borrow(),
+            vec![
+                ("src/user.clj".to_string(), 10, 3),
+                ("src/user.clj".to_string(), 10, 11),
+                ("src/user.clj".to_string(), 11, 8),
+            ]
+        );
+
+        let no_file = SourceLocation {
+            file: None,
+            line: 1,
+            column: None,
+        };
+        assert!(open_location(&launcher, &no_file).is_err());
+        assert_eq!(launcher.opened.borrow().len(), 3);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::profiler::node_duration;
+use crate::state::{ExecTrace, FlowThread};
+use crate::util_types::CallStackTreeNode;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlameWeight {
+    Duration,
+    CallCount,
+}
+
+// A call box in the icicle view, start and width are fractions of the total width
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlameRect {
+    pub trace_idx: usize,
+    pub depth: usize,
+    pub start: f32,
+    pub width: f32,
+    pub label: String,
+}
+
+fn fn_label(traces: &[ExecTrace], node: &CallStackTreeNode) -> String {
+    match &traces[node.trace_idx] {
+        ExecTrace::FnCallTrace(fct) => format!("{}/{}", fct.fn_ns, fct.fn_name),
+        ExecTrace::ExprTrace(_) => panic!("call_stack_tree is pointing to a non FnCallTrace"),
+    }
+}
+
+fn fold_node(
+    traces: &[ExecTrace],
+    node_pointer: &Arc<Mutex<CallStackTreeNode>>,
+    weight: FlameWeight,
+    path: &mut Vec<String>,
+    folded: &mut BTreeMap<String, u64>,
+) {
+    let node = node_pointer.lock().unwrap();
+    path.push(fn_label(traces, &node));
+
+    let node_weight = match weight {
+        FlameWeight::CallCount => 1,
+        // folded stacks are self samples, so only count the time not spent in childs
+        FlameWeight::Duration => node_duration(traces, &node).map_or(0, |d| {
+            let childs_time: u64 = node
+                .childs
+                .iter()
+                .filter_map(|c| node_duration(traces, &c.lock().unwrap()))
+                .sum();
+            d.saturating_sub(childs_time)
+        }),
+    };
+    if node_weight > 0 {
+        *folded.entry(path.join(";")).or_insert(0) += node_weight;
+    }
+
+    for child in &node.childs {
+        fold_node(traces, child, weight, path, folded);
+    }
+    path.pop();
+}
+
+// Brendan Gregg's folded stacks format, one `root;child;leaf weight` line per stack,
+// ready to be fed to flamegraph.pl or inferno
+pub fn folded_stacks(thread: &FlowThread, weight: FlameWeight) -> String {
+    let mut folded = BTreeMap::new();
+    if let Some(cst) = &thread.call_stack_tree {
+        fold_node(
+            &thread.execution.traces,
+            &cst.root,
+            weight,
+            &mut Vec::new(),
+            &mut folded,
+        );
+    }
+    folded
+        .iter()
+        .map(|(stack, w)| format!("{} {}\n", stack, w))
+        .collect()
+}
+
+// Lays out the subtree in call count units, returning its count. The node spans its
+// childs plus one unit for itself.
+fn layout_by_count(
+    traces: &[ExecTrace],
+    node: &CallStackTreeNode,
+    depth: usize,
+    start: usize,
+    rects: &mut Vec<FlameRect>,
+) -> usize {
+    let rect_idx = rects.len();
+    rects.push(FlameRect {
+        trace_idx: node.trace_idx,
+        depth,
+        start: start as f32,
+        width: 0.0,
+        label: fn_label(traces, node),
+    });
+    let mut count = 1;
+    for child in &node.childs {
+        count += layout_by_count(
+            traces,
+            &child.lock().unwrap(),
+            depth + 1,
+            start + count - 1,
+            rects,
+        );
+    }
+    rects[rect_idx].width = count as f32;
+    count
+}
+
+fn layout_by_duration(
+    traces: &[ExecTrace],
+    node: &CallStackTreeNode,
+    depth: usize,
+    (origin, end_of_time, total): (u64, u64, f32),
+    rects: &mut Vec<FlameRect>,
+) {
+    let call_ts = traces[node.trace_idx].timestamp();
+    // calls that never returned last until the end of the recording
+    let ret_ts = node
+        .ret_trace_idx
+        .map_or(end_of_time, |idx| traces[idx].timestamp());
+    rects.push(FlameRect {
+        trace_idx: node.trace_idx,
+        depth,
+        start: call_ts.saturating_sub(origin) as f32 / total,
+        width: ret_ts.saturating_sub(call_ts) as f32 / total,
+        label: fn_label(traces, node),
+    });
+    for child in &node.childs {
+        layout_by_duration(
+            traces,
+            &child.lock().unwrap(),
+            depth + 1,
+            (origin, end_of_time, total),
+            rects,
+        );
+    }
+}
+
+// Lays out the thread call tree as an icicle, root on top
+pub fn icicle_rects(thread: &FlowThread, weight: FlameWeight) -> Vec<FlameRect> {
+    let mut rects = Vec::new();
+    if let Some(cst) = &thread.call_stack_tree {
+        let traces = &thread.execution.traces;
+        let root = cst.root.lock().unwrap();
+        match weight {
+            FlameWeight::CallCount => {
+                let total = layout_by_count(traces, &root, 0, 0, &mut rects) as f32;
+                for r in rects.iter_mut() {
+                    r.start /= total;
+                    r.width /= total;
+                }
+            }
+            FlameWeight::Duration => {
+                let origin = traces[root.trace_idx].timestamp();
+                let end_of_time = traces
+                    .iter()
+                    .map(ExecTrace::timestamp)
+                    .max()
+                    .unwrap_or(origin);
+                let total = u64::max(1, end_of_time.saturating_sub(origin)) as f32;
+                layout_by_duration(traces, &root, 0, (origin, end_of_time, total), &mut rects);
+            }
+        }
+    }
+    rects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{ExprTrace, FnCallTrace};
+
+    fn test_thread() -> FlowThread {
+        let mut thread = FlowThread::new(1);
+        let call = |fn_name: &str, timestamp| {
+            FnCallTrace::new(
+                1,
+                "user".to_string(),
+                fn_name.to_string(),
+                "[]".to_string(),
+                timestamp,
+            )
+        };
+        let ret = |timestamp| ExprTrace::new(1, "nil".to_string(), vec![], true, timestamp);
+
+        // (f (g (h)) (g))
+        thread.add_fn_call_trace(call("f", 0));
+        thread.add_fn_call_trace(call("g", 10));
+        thread.add_fn_call_trace(call("h", 12));
+        thread.add_expr_trace(ret(15));
+        thread.add_expr_trace(ret(20));
+        thread.add_fn_call_trace(call("g", 30));
+        thread.add_expr_trace(ret(40));
+        thread.add_expr_trace(ret(100));
+        thread
+    }
+
+    #[test]
+    fn folded_stacks_test() {
+        let thread = test_thread();
+
+        assert_eq!(
+            folded_stacks(&thread, FlameWeight::Duration),
+            "user/f 80\nuser/f;user/g 17\nuser/f;user/g;user/h 3\n"
+        );
+        assert_eq!(
+            folded_stacks(&thread, FlameWeight::CallCount),
+            "user/f 1\nuser/f;user/g 2\nuser/f;user/g;user/h 1\n"
+        );
+    }
+
+    #[test]
+    fn icicle_rects_test() {
+        let thread = test_thread();
+
+        let rects = icicle_rects(&thread, FlameWeight::CallCount);
+        assert_eq!(
+            rects
+                .iter()
+                .map(|r| (r.trace_idx, r.depth, r.start, r.width))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, 0, 0.0, 1.0),
+                (1, 1, 0.0, 0.5),
+                (2, 2, 0.0, 0.25),
+                (5, 1, 0.5, 0.25)
+            ]
+        );
+
+        let rects = icicle_rects(&thread, FlameWeight::Duration);
+        assert_eq!((rects[1].start, rects[1].width), (0.1, 0.1));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::lisp_reader::{read_str, AtomKind, PrintableLispForm};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::OnceLock;
+
+// User indentation rules for macros the pretty printer doesn't know about, read from
+// an edn file like cljfmt's :indents
+//
+//   {defcomponent [[:defn]]
+//    with-tx      [[:bindings]]
+//    #"^def"      [[:block 1]]}
+//
+// Symbols match qualified or not, regexes match the symbol name.
+
+pub const INDENT_RULES_FILE: &str = "flowstorm-indents.edn";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndentRule {
+    // the first n args on the first line, the rest as a body
+    Block(usize),
+    // everything as a body, like do
+    Inner,
+    // the first n args on the first line, the rest as pairs, like case
+    Pairs(usize),
+    // a bindings vector and a body, like let
+    Bindings,
+    // names and params on the first line, like defn
+    Defn,
+}
+
+impl IndentRule {
+    // Like the built in definitions and bindings, these forms always get broken into lines
+    pub fn always_breaks(&self) -> bool {
+        matches!(self, IndentRule::Bindings | IndentRule::Defn)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct IndentRules {
+    symbols: HashMap<String, IndentRule>,
+    patterns: Vec<(Regex, IndentRule)>,
+}
+
+fn rule_keyword(form: &PrintableLispForm) -> Option<&str> {
+    match form {
+        PrintableLispForm::Atomic(atom, _) => match &atom.kind {
+            AtomKind::Keyword { ns: None, name } => Some(name.as_str()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn rule_arg(form: &PrintableLispForm) -> Option<usize> {
+    match form {
+        PrintableLispForm::Atomic(atom, _) => atom.text.parse().ok(),
+        _ => None,
+    }
+}
+
+// [[:block 1]] as cljfmt writes them, or just [:block 1]. We only have one layout
+// per form, so only the first rule counts.
+fn parse_rule(form: &PrintableLispForm) -> Result<IndentRule, String> {
+    let unknown = || format!("Unknown indentation rule {}", form.to_string());
+    let childs = match form {
+        PrintableLispForm::Vector { childs, .. } => childs,
+        _ => return Err(unknown()),
+    };
+    let rule = match childs.first() {
+        Some(PrintableLispForm::Vector { childs, .. }) => childs,
+        _ => childs,
+    };
+
+    let kw = rule.first().and_then(rule_keyword);
+    let arg = rule.get(1).and_then(rule_arg);
+    match (kw, arg) {
+        (Some("block"), Some(n)) => Ok(IndentRule::Block(n)),
+        (Some("inner"), _) => Ok(IndentRule::Inner),
+        (Some("pairs"), Some(n)) => Ok(IndentRule::Pairs(n)),
+        (Some("bindings"), None) => Ok(IndentRule::Bindings),
+        (Some("defn"), None) => Ok(IndentRule::Defn),
+        _ => Err(unknown()),
+    }
+}
+
+impl IndentRules {
+    pub fn from_edn(edn: &str) -> Result<Self, String> {
+        let (keys, vals) = match read_str(edn) {
+            Ok(PrintableLispForm::Map { keys, vals, .. }) => (keys, vals),
+            Ok(_) => return Err("Indentation rules should be a map".to_string()),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let mut rules = IndentRules::default();
+        for (k, v) in keys.iter().zip(vals.iter()) {
+            let rule = parse_rule(v)?;
+            match k {
+                PrintableLispForm::Atomic(atom, _) if atom.is_symbol() => {
+                    rules.symbols.insert(atom.text.clone(), rule);
+                }
+                PrintableLispForm::Regexp(exp) => {
+                    let re =
+                        Regex::new(exp).map_err(|e| format!("Bad regex #\"{}\": {}", exp, e))?;
+                    rules.patterns.push((re, rule));
+                }
+                _ => {
+                    return Err(format!(
+                        "Rule keys should be symbols or regexes, not {}",
+                        k.to_string()
+                    ))
+                }
+            }
+        }
+        Ok(rules)
+    }
+
+    pub fn load(file_path: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(file_path)
+            .map_err(|e| format!("Couldn't read {}: {}", file_path, e))?;
+        Self::from_edn(&content).map_err(|e| format!("Couldn't load {}: {}", file_path, e))
+    }
+
+    pub fn rule_for(&self, symbol: &str) -> Option<IndentRule> {
+        let name = match symbol.split_once('/') {
+            Some((_, name)) if !name.is_empty() => name,
+            _ => symbol,
+        };
+        self.symbols
+            .get(symbol)
+            .or_else(|| self.symbols.get(name))
+            .copied()
+            .or_else(|| {
+                self.patterns
+                    .iter()
+                    .find(|(re, _)| re.is_match(name))
+                    .map(|(_, rule)| *rule)
+            })
+    }
+}
+
+static INDENT_RULES: OnceLock<IndentRules> = OnceLock::new();
+
+// Only the first call wins, it is meant to be called once at startup
+pub fn set_indent_rules(rules: IndentRules) {
+    let _ = INDENT_RULES.set(rules);
+}
+
+// The rules the pretty printer uses, none until set_indent_rules gets called
+pub fn indent_rules() -> &'static IndentRules {
+    INDENT_RULES.get_or_init(IndentRules::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indent_rules_test() {
+        let rules = IndentRules::from_edn(
+            "{defcomponent [[:defn]]
+              my.db/with-tx [:bindings]
+              match [[:pairs 1]]
+              #\"^def\" [[:block 1]]
+              #\"^with-\" [[:inner 0]]}",
+        )
+        .unwrap();
+
+        assert_eq!(rules.rule_for("defcomponent"), Some(IndentRule::Defn));
+        assert_eq!(rules.rule_for("ui/defcomponent"), Some(IndentRule::Defn));
+        assert_eq!(rules.rule_for("my.db/with-tx"), Some(IndentRule::Bindings));
+        assert_eq!(rules.rule_for("with-tx"), Some(IndentRule::Inner));
+        assert_eq!(rules.rule_for("match"), Some(IndentRule::Pairs(1)));
+        assert_eq!(rules.rule_for("defroutes"), Some(IndentRule::Block(1)));
+        assert_eq!(
+            rules.rule_for("compojure/defroutes"),
+            Some(IndentRule::Block(1))
+        );
+        assert_eq!(rules.rule_for("inc"), None);
+
+        assert!(IndentRules::from_edn("{foo [[:wat 1]]}").is_err());
+        assert!(IndentRules::from_edn("{:foo [[:block 1]]}").is_err());
+        assert!(IndentRules::from_edn("{#\"(\" [[:block 1]]}").is_err());
+        assert!(IndentRules::from_edn("[foo]").is_err());
+    }
+}
@@ -1,603 +1,934 @@
 /***********************************************************
 
-- can we improve all with visitors pattern?
+Forms are laid out with a document algebra in the style of
+Wadler's "A prettier printer" and Leijen's wl-pprint. Every form
+becomes a Doc, where groups print flat when they fit in the rest
+of the line and break all their lines otherwise. The Clojure
+styles below are just different ways of combining the childs docs.
 
 ***********************************************************/
 
-use crate::lisp_reader::PrintStyle;
+use crate::indent_rules::{indent_rules, IndentRule, IndentRules};
 use crate::lisp_reader::PrintableLispForm;
+use crate::lisp_reader::{Atom, AtomKind, SourceSpan, SpanKind, Truncation};
+use std::collections::HashMap;
+use std::rc::Rc;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum PrintToken {
     String(String),
     Regexp(String),
-    BlockOpen { val: String, coord: Vec<u16> },
-    BlockClose { val: String, coord: Vec<u16> },
-    Atomic { val: String, coord: Vec<u16> },
+    BlockOpen {
+        val: String,
+        coord: Vec<u16>,
+    },
+    BlockClose {
+        val: String,
+        coord: Vec<u16>,
+    },
+    // kind is None for the syntax around forms, like tags, ^ and #'
+    Atomic {
+        val: String,
+        coord: Vec<u16>,
+        kind: Option<AtomKind>,
+    },
+    // only in sources, comments and any other text outside of the forms, like commas
+    Comment(String),
+    Text(String),
     Space,
     Newline,
-    PrintTokensVec(Vec<PrintToken>),
 }
 
 const INDENT_WIDTH: usize = 2;
 
-////////////////////
-// Some utilities //
-////////////////////
+///////////////
+// Documents //
+///////////////
 
-fn indented_nl(width: usize) -> Vec<PrintToken> {
-    let mut v: Vec<PrintToken> = Vec::new();
-    v.push(PrintToken::Newline);
-    for _i in 0..width {
-        v.push(PrintToken::Space)
-    }
-    v
+// Docs are shared with Rc since choices hold the same childs in both layouts
+#[derive(Debug, Clone)]
+enum Doc {
+    Text(PrintToken),
+    // a space, or a newline when the enclosing group is broken
+    Line,
+    // always a newline, so every enclosing group gets broken. Only printed as a
+    // space when the whole document is printed in one line.
+    HardLine,
+    // indents the lines inside n more columns
+    Nest(usize, Rc<Doc>),
+    // indents the lines inside at the current column
+    Align(Rc<Doc>),
+    // prints flat when it fits in the rest of the line
+    Group(Rc<Doc>),
+    // always prints flat
+    Flat(Rc<Doc>),
+    // the first layout, unless it doesn't fit or the second one takes less lines.
+    // Both should print the same when flat.
+    Choice(Rc<Doc>, Rc<Doc>),
+    Concat(Vec<Doc>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+fn text(t: PrintToken) -> Doc {
+    Doc::Text(t)
+}
+
+fn nest(n: usize, doc: Doc) -> Doc {
+    Doc::Nest(n, Rc::new(doc))
+}
+
+fn align(doc: Doc) -> Doc {
+    Doc::Align(Rc::new(doc))
+}
+
+fn group(doc: Doc) -> Doc {
+    Doc::Group(Rc::new(doc))
+}
+
+fn flat(doc: Doc) -> Doc {
+    Doc::Flat(Rc::new(doc))
+}
+
+fn choice(a: Doc, b: Doc) -> Doc {
+    Doc::Choice(Rc::new(a), Rc::new(b))
 }
 
-// TODO: this can be replaced by iterator intersperse
-fn join(pt: PrintToken, tokens: Vec<PrintToken>) -> Vec<PrintToken> {
-    let mut r: Vec<PrintToken> = Vec::new();
-    for t in tokens.iter().cloned() {
-        r.push(t);
-        r.push(pt.clone());
+fn join(docs: impl IntoIterator<Item = Doc>, sep: &Doc) -> Doc {
+    let mut r = Vec::new();
+    for d in docs {
+        if !r.is_empty() {
+            r.push(sep.clone());
+        }
+        r.push(d);
     }
-    r.pop();
-    r
+    Doc::Concat(r)
 }
 
-fn join_coll(v: Vec<PrintToken>, tokens: Vec<PrintToken>) -> Vec<PrintToken> {
-    let mut r: Vec<PrintToken> = Vec::new();
-    for t in tokens.iter().cloned() {
-        r.push(t);
-        for vt in v.iter().cloned() {
-            r.push(vt);
+// Docs filling the lines, every doc goes in a new line only when it doesn't fit
+// in the current one
+fn fill(docs: impl IntoIterator<Item = Doc>) -> Doc {
+    let mut r = Vec::new();
+    for d in docs {
+        if r.is_empty() {
+            r.push(d);
+        } else {
+            r.push(group(Doc::Concat(vec![Doc::Line, d])));
         }
     }
-    for _i in 0..v.len() {
-        r.pop();
+    Doc::Concat(r)
+}
+
+fn token_width(t: &PrintToken) -> usize {
+    match t {
+        PrintToken::String(s) => s.chars().count() + 2,
+        PrintToken::Regexp(exp) => exp.chars().count() + 3,
+        PrintToken::BlockOpen { val, .. }
+        | PrintToken::BlockClose { val, .. }
+        | PrintToken::Atomic { val, .. }
+        | PrintToken::Comment(val)
+        | PrintToken::Text(val) => val.chars().count(),
+        PrintToken::Space => 1,
+        PrintToken::Newline => 0,
     }
+}
 
-    r
+// (indent, mode, doc) still to be printed, the next one at the end
+type Cmds<'a> = Vec<(usize, Mode, &'a Doc)>;
+
+// Whether doc printed flat, followed by the rest of the docs to print up to the
+// next newline, fits in width
+fn fits(width: usize, doc: &Doc, rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut width = width;
+    let mut rest = rest.iter().rev();
+    let mut stack = vec![(Mode::Flat, doc)];
+    loop {
+        let (mode, doc) = match stack.pop() {
+            Some(cmd) => cmd,
+            None => match rest.next() {
+                Some((_, mode, doc)) => (*mode, *doc),
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Text(t) => match width.checked_sub(token_width(t)) {
+                Some(w) => width = w,
+                None => return false,
+            },
+            Doc::Line if mode == Mode::Flat => match width.checked_sub(1) {
+                Some(w) => width = w,
+                None => return false,
+            },
+            Doc::Line => return true,
+            Doc::HardLine => return mode == Mode::Break,
+            Doc::Flat(d) => stack.push((Mode::Flat, d)),
+            Doc::Nest(_, d) | Doc::Align(d) | Doc::Group(d) | Doc::Choice(d, _) => {
+                stack.push((mode, d))
+            }
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|d| (mode, d))),
+        }
+    }
 }
 
-////////////////////
-// Implementation //
-////////////////////
+// Where a layout tried at some column ends
+#[derive(Debug, Clone, Copy)]
+struct LayoutEnd {
+    col: usize,
+    lines: usize,
+}
 
-fn n_symbol_print(
-    childs: &Vec<PrintableLispForm>,
-    n: usize,
-    left: usize,
-) -> (Vec<PrintToken>, usize) {
-    let elem = childs.as_slice().get(n).unwrap();
-    let toks = lisp_form_print_tokens_aux(elem, left);
-    let toks_width = tokens_width(&flatten_print_tokens(&toks));
-    (toks, toks_width)
+struct Printer {
+    width: usize,
+    // layouts already tried, by doc address, indent and column
+    tried: HashMap<(usize, usize, usize), Option<LayoutEnd>>,
 }
 
-fn flatten_print_tokens(tokens: &Vec<PrintToken>) -> Vec<PrintToken> {
-    let mut r: Vec<PrintToken> = Vec::new();
-    for pt in tokens.iter().cloned() {
-        match &pt {
-            PrintToken::String(_) => {
-                r.push(pt);
-            }
-            PrintToken::Regexp(_) => {
-                r.push(pt);
-            }
-            PrintToken::BlockOpen { val: _, coord: _ } => {
-                r.push(pt);
-            }
-            PrintToken::BlockClose { val: _, coord: _ } => {
-                r.push(pt);
-            }
-            PrintToken::Atomic { val: _, coord: _ } => {
-                r.push(pt);
-            }
-            PrintToken::Space => {
-                r.push(pt);
-            }
-            PrintToken::Newline => {
-                r.push(pt);
-            }
-            PrintToken::PrintTokensVec(v) => {
-                for t in flatten_print_tokens(&v).iter().cloned() {
-                    r.push(t);
+impl Printer {
+    fn new(width: usize) -> Self {
+        Printer {
+            width,
+            tried: HashMap::new(),
+        }
+    }
+
+    // Prints cmds starting at col. Without out it only tries the layout, giving up
+    // as soon as a line doesn't fit.
+    fn print(
+        &mut self,
+        mut cmds: Cmds,
+        mut col: usize,
+        mut out: Option<&mut Vec<PrintToken>>,
+    ) -> Option<LayoutEnd> {
+        let trying = out.is_none();
+        let mut lines = 0;
+        while let Some((indent, mode, doc)) = cmds.pop() {
+            match doc {
+                Doc::Text(t) => {
+                    col += token_width(t);
+                    if let Some(out) = out.as_mut() {
+                        out.push(t.clone());
+                    }
+                }
+                Doc::HardLine if trying && mode == Mode::Flat => return None,
+                Doc::Line | Doc::HardLine if mode == Mode::Flat => {
+                    col += 1;
+                    if let Some(out) = out.as_mut() {
+                        out.push(PrintToken::Space);
+                    }
                 }
+                Doc::Line | Doc::HardLine => {
+                    col = indent;
+                    lines += 1;
+                    if let Some(out) = out.as_mut() {
+                        out.push(PrintToken::Newline);
+                        out.extend(std::iter::repeat_n(PrintToken::Space, indent));
+                    }
+                }
+                Doc::Nest(n, d) => cmds.push((indent + n, mode, d)),
+                Doc::Align(d) => cmds.push((col, mode, d)),
+                Doc::Flat(d) => cmds.push((indent, Mode::Flat, d)),
+                Doc::Group(d) => {
+                    let mode = match mode {
+                        Mode::Break if !fits(self.width.saturating_sub(col), d, &cmds) => {
+                            Mode::Break
+                        }
+                        _ => Mode::Flat,
+                    };
+                    cmds.push((indent, mode, d));
+                }
+                Doc::Choice(a, b) => {
+                    let d = if mode == Mode::Flat || self.choose(a, b, indent, col, &cmds) {
+                        a
+                    } else {
+                        b
+                    };
+                    cmds.push((indent, mode, d));
+                }
+                Doc::Concat(docs) => cmds.extend(docs.iter().rev().map(|d| (indent, mode, d))),
+            }
+            if trying && col > self.width {
+                return None;
             }
         }
+        Some(LayoutEnd { col, lines })
     }
+
+    // How doc ends when all its lines fit, including what follows it in the last one
+    fn try_layout(
+        &mut self,
+        doc: &Doc,
+        indent: usize,
+        col: usize,
+        rest: &[(usize, Mode, &Doc)],
+    ) -> Option<LayoutEnd> {
+        let key = (doc as *const Doc as usize, indent, col);
+        if let Some(end) = self.tried.get(&key) {
+            return *end;
+        }
+        let end = self
+            .print(vec![(indent, Mode::Break, doc)], col, None)
+            .filter(|end| {
+                fits(
+                    self.width.saturating_sub(end.col),
+                    &Doc::Concat(Vec::new()),
+                    rest,
+                )
+            });
+        self.tried.insert(key, end);
+        end
+    }
+
+    fn choose(
+        &mut self,
+        a: &Doc,
+        b: &Doc,
+        indent: usize,
+        col: usize,
+        rest: &[(usize, Mode, &Doc)],
+    ) -> bool {
+        match self.try_layout(a, indent, col, rest) {
+            Some(a_end) => match self.try_layout(b, indent, col, rest) {
+                Some(b_end) => a_end.lines <= b_end.lines,
+                None => true,
+            },
+            None => false,
+        }
+    }
+}
+
+fn render(doc: &Doc, width: usize, mode: Mode) -> Vec<PrintToken> {
+    let mut r = Vec::new();
+    Printer::new(width).print(vec![(0, mode, doc)], 0, Some(&mut r));
     r
 }
 
-fn tokens_width(tokens: &Vec<PrintToken>) -> usize {
-    tokens
-        .as_slice()
-        .rsplit(|pt| {
-            if let PrintToken::Newline = pt {
-                true
-            } else {
-                false
-            }
+///////////////////////////
+// Forms as documents    //
+///////////////////////////
+
+// How the childs of a list are laid out, picked from its head
+#[derive(Debug, Clone, PartialEq)]
+enum PrintStyle {
+    // pairs aligned one per line, like map entries or let bindings
+    PairsBlock,
+    // Block(1) with the first arg vector as a PairsBlock
+    Binding,
+    // the head and its first n args on the first line, the rest indented as a body
+    Block(usize),
+    // like Block(n) but the body goes in pairs, like the clauses of a cond or case
+    PairsBody(usize),
+    Standard,
+}
+
+// Collections are aligned at their open delimiter, so nested bodies indent from there
+fn delimited_doc(open: &str, close: &str, coord: &[u16], body: Doc) -> Doc {
+    group(align(Doc::Concat(vec![
+        text(PrintToken::BlockOpen {
+            val: open.to_string(),
+            coord: coord.to_vec(),
+        }),
+        body,
+        text(PrintToken::BlockClose {
+            val: close.to_string(),
+            coord: coord.to_vec(),
+        }),
+    ])))
+}
+
+// The val next to the key, or under it when it doesn't fit
+fn pair_doc(k: Doc, v: Doc) -> Doc {
+    choice(
+        Doc::Concat(vec![flat(k.clone()), text(PrintToken::Space), v.clone()]),
+        Doc::Concat(vec![k, nest(INDENT_WIDTH, Doc::Concat(vec![Doc::Line, v]))]),
+    )
+}
+
+// Forms two by two. With an odd number of forms the last one goes alone, like the
+// default of a case.
+fn pairs_docs(forms: &[PrintableLispForm], rules: &IndentRules) -> Vec<Doc> {
+    forms
+        .chunks(2)
+        .map(|part| match part {
+            [k, v] => pair_doc(form_doc(k, rules), form_doc(v, rules)),
+            _ => form_doc(&part[0], rules),
         })
-        .map(|pt_line| {
-            pt_line
+        .collect()
+}
+
+// The head and its first n args on the first line, followed by the rest of the
+// childs as a body
+fn block_doc(head: Vec<Doc>, rest: Vec<Doc>, line: &Doc) -> Doc {
+    let head = join(head, &text(PrintToken::Space));
+    if rest.is_empty() {
+        return head;
+    }
+    let mut body = vec![line.clone()];
+    body.push(join(rest, line));
+    Doc::Concat(vec![head, nest(INDENT_WIDTH, Doc::Concat(body))])
+}
+
+// The childs of a List, lines are hard for the styles that always break
+fn seq_doc(
+    childs: &[PrintableLispForm],
+    style: &PrintStyle,
+    line: &Doc,
+    rules: &IndentRules,
+) -> Doc {
+    let docs = |forms: &[PrintableLispForm]| {
+        forms
+            .iter()
+            .map(|c| form_doc(c, rules))
+            .collect::<Vec<Doc>>()
+    };
+    let split = |n: usize| childs.split_at(usize::min(n + 1, childs.len()));
+
+    match style {
+        // args aligned after the head, or one column in when they don't fit there
+        PrintStyle::Standard => match childs.split_first() {
+            Some((head, args)) if !args.is_empty() => {
+                let head = form_doc(head, rules);
+                let args = join(docs(args), line);
+                choice(
+                    Doc::Concat(vec![
+                        head.clone(),
+                        text(PrintToken::Space),
+                        align(args.clone()),
+                    ]),
+                    Doc::Concat(vec![head, nest(1, Doc::Concat(vec![line.clone(), args]))]),
+                )
+            }
+            _ => Doc::Concat(docs(childs)),
+        },
+
+        PrintStyle::Block(n) => {
+            let (head, rest) = split(*n);
+            block_doc(docs(head), docs(rest), line)
+        }
+
+        PrintStyle::PairsBody(n) => {
+            let (head, rest) = split(*n);
+            block_doc(docs(head), pairs_docs(rest, rules), line)
+        }
+
+        PrintStyle::PairsBlock => align(join(pairs_docs(childs, rules), line)),
+
+        PrintStyle::Binding => {
+            let (head, rest) = split(1);
+            let head = head
                 .iter()
-                .map(|pt| match pt {
-                    PrintToken::String(s) => s.len(),
-                    PrintToken::Regexp(exp) => exp.len() + 1,
-                    PrintToken::BlockOpen {
-                        ref val,
-                        coord: _coord,
-                    } => val.len(),
-                    PrintToken::BlockClose {
-                        ref val,
-                        coord: _coord,
-                    } => val.len(),
-                    PrintToken::Atomic {
-                        ref val,
-                        coord: _coord,
-                    } => val.len(),
-                    PrintToken::Space => 1,
-                    PrintToken::Newline => 1,
-                    PrintToken::PrintTokensVec(_v) => {
-                        panic!("tokens_width only work with a flat Vec<PrintToken")
-                    }
+                .map(|c| match c {
+                    PrintableLispForm::Vector { childs, coord, .. } => delimited_doc(
+                        "[",
+                        "]",
+                        coord,
+                        seq_doc(childs, &PrintStyle::PairsBlock, line, rules),
+                    ),
+                    _ => form_doc(c, rules),
                 })
-                .sum()
-        })
-        .max()
-        .unwrap()
+                .collect();
+            block_doc(head, docs(rest), line)
+        }
+    }
 }
 
-fn lisp_form_seq_print_tokens(pform: &PrintableLispForm, left: usize) -> Vec<PrintToken> {
+// Definitions and bindings always break, the rest only when they don't fit
+fn list_doc(childs: &[PrintableLispForm], rules: &IndentRules) -> Doc {
+    match definition_style(childs, rules) {
+        Some(style) => seq_doc(childs, &style, &Doc::HardLine, rules),
+        None => seq_doc(childs, &breaking_style(childs, rules), &Doc::Line, rules),
+    }
+}
+
+// Items filling the lines, like the elements of a vector
+fn fill_doc(childs: &[PrintableLispForm], rules: &IndentRules) -> Doc {
+    align(fill(childs.iter().map(|c| form_doc(c, rules))))
+}
+
+fn form_doc(pform: &PrintableLispForm, rules: &IndentRules) -> Doc {
     match pform {
-        PrintableLispForm::List {
-            childs,
-            style,
-            coord: _coord,
+        PrintableLispForm::List { childs, coord, .. } => {
+            delimited_doc("(", ")", coord, list_doc(childs, rules))
         }
-        | PrintableLispForm::Vector {
-            childs,
-            style,
-            coord: _coord,
+
+        PrintableLispForm::Vector { childs, coord, .. } => {
+            delimited_doc("[", "]", coord, fill_doc(childs, rules))
         }
-        | PrintableLispForm::Set {
-            childs,
-            style,
-            coord: _coord,
+
+        PrintableLispForm::Set { childs, coord, .. } => {
+            delimited_doc("#{", "}", coord, fill_doc(childs, rules))
+        }
+
+        PrintableLispForm::Map {
+            keys,
+            vals,
+            truncated,
+            coord,
+            ..
         } => {
-            if childs.is_empty() {
-                return Vec::new();
-            } else {
-                match style {
-                    PrintStyle::Unstyled | PrintStyle::Linear => {
-                        // HACKY: prints this level linear and assumes that all forms
-                        // down the tree are going to be unstyled, so everything will print linear
-                        return join(
-                            PrintToken::Space,
-                            childs
-                                .iter()
-                                .map(|lf| {
-                                    PrintToken::PrintTokensVec(lisp_form_print_tokens_aux(lf, left))
-                                })
-                                .collect::<Vec<PrintToken>>(),
-                        );
-                    }
+            let pairs = keys
+                .iter()
+                .zip(vals)
+                .map(|(k, v)| pair_doc(form_doc(k, rules), form_doc(v, rules)))
+                .chain(truncated.map(|t| form_doc(&PrintableLispForm::Truncated(t), rules)));
+            delimited_doc("{", "}", coord, align(join(pairs, &Doc::Line)))
+        }
 
-                    PrintStyle::Standard => {
-                        // length of the first thing on the List, Vector, Set
-                        let (_, flen) = n_symbol_print(childs, 0, left);
-                        childs
-                            .iter()
-                            .enumerate()
-                            .map(|(i, pf)| match i {
-                                0 => {
-                                    PrintToken::PrintTokensVec(lisp_form_print_tokens_aux(pf, left))
-                                }
-                                1 => {
-                                    let mut r: Vec<PrintToken> = Vec::new();
-                                    let mut v = lisp_form_print_tokens_aux(pf, left + flen + 1);
-                                    r.push(PrintToken::Space);
-                                    r.append(&mut v);
-                                    PrintToken::PrintTokensVec(r)
-                                }
-                                _ => {
-                                    let mut r: Vec<PrintToken> = Vec::new();
-                                    let mut iln = indented_nl(left + flen + 2);
-                                    let mut v = lisp_form_print_tokens_aux(pf, left + flen + 2);
-                                    r.append(&mut iln);
-                                    r.append(&mut v);
-                                    PrintToken::PrintTokensVec(r)
-                                }
-                            })
-                            .collect::<Vec<PrintToken>>()
-                    }
+        PrintableLispForm::String(s) => text(PrintToken::String(s.to_string())),
 
-                    PrintStyle::Defn => {
-                        let (a_toks, a_len) = n_symbol_print(childs, 0, left);
-                        let (b_toks, b_len) = n_symbol_print(childs, 1, left + a_len + 1);
-                        let (c_toks, _) = n_symbol_print(childs, 2, left + a_len + b_len + 1);
-                        let rest = childs.iter().skip(3);
-                        return vec![
-                            PrintToken::PrintTokensVec(a_toks),
-                            PrintToken::Space,
-                            PrintToken::PrintTokensVec(b_toks),
-                            PrintToken::Space,
-                            PrintToken::PrintTokensVec(c_toks),
-                            PrintToken::Space,
-                            PrintToken::PrintTokensVec(
-                                rest.map(|lf| {
-                                    // TODO: we can factor this out since it is also used in Standard
-                                    let mut r: Vec<PrintToken> = Vec::new();
-                                    let mut iln = indented_nl(left + INDENT_WIDTH);
-                                    let mut v = lisp_form_print_tokens_aux(lf, left + INDENT_WIDTH);
-                                    r.append(&mut iln);
-                                    r.append(&mut v);
-                                    PrintToken::PrintTokensVec(r)
-                                })
-                                .collect::<Vec<PrintToken>>(),
-                            ),
-                        ];
-                    }
+        PrintableLispForm::Regexp(exp) => text(PrintToken::Regexp(exp.to_string())),
 
-                    PrintStyle::PairsBlock => {
-                        let parts = childs
-                            .chunks(2)
-                            .map(|part| {
-                                let lf1 = &part[0];
-                                let lf2 = &part[1];
-                                let mut lf1c = lf1.clone();
-                                match lf1c {
-                                    PrintableLispForm::List {
-                                        childs: ref _childs,
-                                        ref mut style,
-                                        coord: ref _coord,
-                                    }
-                                    | PrintableLispForm::Vector {
-                                        childs: ref _childs,
-                                        ref mut style,
-                                        coord: ref _coord,
-                                    }
-                                    | PrintableLispForm::Set {
-                                        childs: ref _childs,
-                                        ref mut style,
-                                        coord: ref _coord,
-                                    } => {
-                                        *style = PrintStyle::Linear;
-                                    }
-                                    _ => (),
-                                };
-
-                                let lf1_toks = lisp_form_print_tokens_aux(&lf1c, left);
-                                let lf1_len = tokens_width(&flatten_print_tokens(&lf1_toks));
-                                let lf2_toks = lisp_form_print_tokens_aux(&lf2, left + 1 + lf1_len);
-
-                                let mut r: Vec<PrintToken> = Vec::new();
-                                r.push(PrintToken::PrintTokensVec(lf1_toks));
-                                r.push(PrintToken::Space);
-                                r.push(PrintToken::PrintTokensVec(lf2_toks));
-                                PrintToken::PrintTokensVec(r)
-                            })
-                            .collect::<Vec<PrintToken>>();
-
-                        return join_coll(indented_nl(left + INDENT_WIDTH), parts);
-                    }
+        PrintableLispForm::Atomic(atom, coord) => text(PrintToken::Atomic {
+            val: atom.text.clone(),
+            coord: coord.to_vec(),
+            kind: Some(atom.kind.clone()),
+        }),
 
-                    PrintStyle::Binding => {
-                        let (symb_toks, symb_len) = n_symbol_print(childs, 0, left);
-                        let mut bvec_form = childs.iter().skip(1).next().unwrap().clone();
-
-                        match bvec_form {
-                            PrintableLispForm::Vector {
-                                childs: ref _childs,
-                                ref mut style,
-                                coord: ref _coord,
-                            } => {
-                                *style = PrintStyle::PairsBlock;
-                            }
-                            _ => (),
-                        };
-                        let bvec_toks = lisp_form_print_tokens_aux(&bvec_form, left + symb_len + 2);
-
-                        let rest = childs.iter().skip(2);
-
-                        vec![
-                            PrintToken::PrintTokensVec(symb_toks),
-                            PrintToken::Space,
-                            PrintToken::PrintTokensVec(bvec_toks),
-                            PrintToken::Space,
-                            PrintToken::PrintTokensVec(
-                                rest.map(|lf| {
-                                    // TODO: we can factor this out since it is also used in Standard
-                                    let mut r: Vec<PrintToken> = Vec::new();
-                                    let mut iln = indented_nl(left + INDENT_WIDTH);
-                                    let mut v = lisp_form_print_tokens_aux(lf, left + INDENT_WIDTH);
-                                    r.append(&mut iln);
-                                    r.append(&mut v);
-                                    PrintToken::PrintTokensVec(r)
-                                })
-                                .collect::<Vec<PrintToken>>(),
-                            ),
-                        ]
-                    }
-                }
-            }
-        }
-        _ => panic!("lisp_form_seq_print_tokens is only for List, Vector or Set"),
-    }
-}
-
-fn map_body_print_tokens(pform: &PrintableLispForm, left: usize) -> Vec<PrintToken> {
-    if let PrintableLispForm::Map {
-        keys,
-        vals,
-        style: _,
-        coord,
-    } = pform
-    {
-        // Kind of hacky but if we interleave keys and vals we can print the map body as a list in PairsBlock style
-        return lisp_form_seq_print_tokens(
-            &PrintableLispForm::List {
-                childs: keys
-                    .iter()
-                    .zip(vals)
-                    .flat_map(|(x, y)| {
-                        let v: Vec<PrintableLispForm> = vec![x.clone(), y.clone()];
-                        v
-                    })
-                    .collect::<Vec<PrintableLispForm>>(),
-                style: PrintStyle::PairsBlock,
+        PrintableLispForm::Tagged { tag, form, coord } => Doc::Concat(vec![
+            text(PrintToken::Atomic {
+                val: format!("#{}", tag),
                 coord: coord.to_vec(),
-            },
-            left,
-        );
-    } else {
-        return Vec::new();
-    }
-}
+                kind: None,
+            }),
+            text(PrintToken::Space),
+            form_doc(form, rules),
+        ]),
 
-fn lisp_form_print_tokens_aux(pform: &PrintableLispForm, left: usize) -> Vec<PrintToken> {
-    match pform {
-        PrintableLispForm::List {
-            childs: _,
-            style: _,
+        PrintableLispForm::ReaderMacro {
+            prefix,
+            form,
             coord,
-        } => vec![
-            PrintToken::BlockOpen {
-                val: "(".to_string(),
+        } => Doc::Concat(vec![
+            text(PrintToken::Atomic {
+                val: prefix.to_string(),
                 coord: coord.to_vec(),
-            },
-            PrintToken::PrintTokensVec(lisp_form_seq_print_tokens(pform, left)),
-            PrintToken::BlockClose {
-                val: ")".to_string(),
-                coord: coord.to_vec(),
-            },
-        ],
+                kind: None,
+            }),
+            form_doc(form, rules),
+        ]),
 
-        PrintableLispForm::Vector {
-            childs: _,
-            style: _,
-            coord,
-        } => vec![
-            PrintToken::BlockOpen {
-                val: "[".to_string(),
-                coord: coord.to_vec(),
-            },
-            PrintToken::PrintTokensVec(lisp_form_seq_print_tokens(pform, left)),
-            PrintToken::BlockClose {
-                val: "]".to_string(),
-                coord: coord.to_vec(),
-            },
-        ],
+        PrintableLispForm::AnonFn { body, coord } => {
+            let body = match &**body {
+                PrintableLispForm::List { childs, .. } => list_doc(childs, rules),
+                _ => form_doc(body, rules),
+            };
+            delimited_doc("#(", ")", coord, body)
+        }
 
-        PrintableLispForm::Set {
-            childs: _,
-            style: _,
-            coord,
-        } => vec![
-            PrintToken::BlockOpen {
-                val: "#{".to_string(),
-                coord: coord.to_vec(),
-            },
-            PrintToken::PrintTokensVec(lisp_form_seq_print_tokens(pform, left)),
-            PrintToken::BlockClose {
-                val: "}".to_string(),
-                coord: coord.to_vec(),
-            },
-        ],
+        PrintableLispForm::Meta { meta, form } => Doc::Concat(vec![
+            text(PrintToken::Atomic {
+                val: "^".to_string(),
+                coord: form_coord(meta),
+                kind: None,
+            }),
+            form_doc(meta, rules),
+            text(PrintToken::Space),
+            form_doc(form, rules),
+        ]),
 
-        PrintableLispForm::Map {
-            keys: _,
-            vals: _,
-            style: _,
+        PrintableLispForm::ReaderConditional {
+            splicing,
+            keys,
+            vals,
+            selected: _,
             coord,
-        } => vec![
-            PrintToken::BlockOpen {
-                val: "{".to_string(),
-                coord: coord.to_vec(),
-            },
-            PrintToken::PrintTokensVec(map_body_print_tokens(pform, left)),
-            PrintToken::BlockClose {
-                val: "}".to_string(),
-                coord: coord.to_vec(),
-            },
-        ],
-
-        PrintableLispForm::String(s) => vec![PrintToken::String(s.to_string())],
+        } => {
+            let open = if *splicing { "#?@(" } else { "#?(" };
+            let pairs = keys
+                .iter()
+                .zip(vals)
+                .map(|(k, v)| pair_doc(form_doc(k, rules), form_doc(v, rules)));
+            delimited_doc(open, ")", coord, align(join(pairs, &Doc::Line)))
+        }
 
-        PrintableLispForm::Regexp(exp) => vec![PrintToken::Regexp(exp.to_string())],
+        PrintableLispForm::NamespacedMap { ns, map } => Doc::Concat(vec![
+            text(PrintToken::Atomic {
+                val: format!("#:{}", ns),
+                coord: form_coord(map),
+                kind: None,
+            }),
+            form_doc(map, rules),
+        ]),
 
-        PrintableLispForm::Atomic(s, coord) => vec![PrintToken::Atomic {
-            val: s.to_string(),
-            coord: coord.to_vec(),
-        }],
+        PrintableLispForm::Truncated(_) => text(PrintToken::Atomic {
+            val: pform.to_string(),
+            coord: Vec::new(),
+            kind: None,
+        }),
 
-        PrintableLispForm::Tagged { tag, form, coord } => {
-            vec![
-                PrintToken::Atomic {
-                    val: tag.to_string(),
-                    coord: coord.to_vec(),
-                },
-                PrintToken::PrintTokensVec(lisp_form_print_tokens_aux(form, left)),
-            ]
+        PrintableLispForm::TruncatedString { prefix, .. } => {
+            text(PrintToken::String(format!("{}...", prefix)))
         }
     }
 }
 
+// Prefixes like ^ or #: are highlighted with the coord of the form they prefix.
+// Strings and regexps don't carry a coord.
+fn form_coord(pform: &PrintableLispForm) -> Vec<u16> {
+    match pform {
+        PrintableLispForm::Atomic(_, coord)
+        | PrintableLispForm::List { coord, .. }
+        | PrintableLispForm::Vector { coord, .. }
+        | PrintableLispForm::Set { coord, .. }
+        | PrintableLispForm::Map { coord, .. }
+        | PrintableLispForm::Tagged { coord, .. }
+        | PrintableLispForm::ReaderMacro { coord, .. }
+        | PrintableLispForm::AnonFn { coord, .. }
+        | PrintableLispForm::ReaderConditional { coord, .. } => coord.to_vec(),
+        PrintableLispForm::Meta { form, .. } => form_coord(form),
+        PrintableLispForm::NamespacedMap { map, .. } => form_coord(map),
+        PrintableLispForm::String(_)
+        | PrintableLispForm::Regexp(_)
+        | PrintableLispForm::Truncated(_)
+        | PrintableLispForm::TruncatedString { .. } => Vec::new(),
+    }
+}
+
+fn head_symbol(childs: &[PrintableLispForm]) -> Option<&str> {
+    match childs.first() {
+        Some(PrintableLispForm::Atomic(atom, _)) if atom.is_symbol() => Some(atom.text.as_str()),
+        _ => None,
+    }
+}
+
+// Keeps the names and the params vector on the first line, like (defn f [x] or
+// (catch Exception e, while docstrings and multiple arities go in the body
+fn params_line_style(childs: &[PrintableLispForm]) -> PrintStyle {
+    let names = childs
+        .iter()
+        .skip(1)
+        .take_while(|c| matches!(c, PrintableLispForm::Atomic(..)))
+        .count();
+    match childs.get(names + 1) {
+        Some(PrintableLispForm::Vector { .. }) => PrintStyle::Block(names + 1),
+        _ => PrintStyle::Block(names),
+    }
+}
+
+fn rule_style(rule: IndentRule, childs: &[PrintableLispForm]) -> PrintStyle {
+    match rule {
+        IndentRule::Block(n) => PrintStyle::Block(n),
+        IndentRule::Inner => PrintStyle::Block(0),
+        IndentRule::Pairs(n) => PrintStyle::PairsBody(n),
+        IndentRule::Bindings => PrintStyle::Binding,
+        IndentRule::Defn => params_line_style(childs),
+    }
+}
+
+// Definitions and binding forms are always broken into lines. User rules win over
+// the built in ones.
+fn definition_style(childs: &[PrintableLispForm], rules: &IndentRules) -> Option<PrintStyle> {
+    let head = head_symbol(childs)?;
+    if let Some(rule) = rules.rule_for(head) {
+        return rule.always_breaks().then(|| rule_style(rule, childs));
+    }
+    match head {
+        "defn" | "defn-" | "defmacro" | "defmethod" | "defrecord" | "deftype" | "defprotocol"
+        | "extend-protocol" | "extend-type" | "ns" => Some(params_line_style(childs)),
+        "let" | "binding" | "loop" | "for" | "doseq" | "dotimes" | "letfn" | "when-let"
+        | "if-let" | "when-some" | "if-some" | "when-first" | "with-open" | "with-redefs"
+        | "with-local-vars" => Some(PrintStyle::Binding),
+        _ => None,
+    }
+}
+
+// How the rest of the forms are broken when they don't fit, following the Clojure style
+// guide. Threading macros and fn calls align their args, which is the Standard style.
+fn breaking_style(childs: &[PrintableLispForm], rules: &IndentRules) -> PrintStyle {
+    if let Some(rule) = head_symbol(childs).and_then(|head| rules.rule_for(head)) {
+        return rule_style(rule, childs);
+    }
+    match head_symbol(childs) {
+        Some("fn" | "fn*" | "reify" | "proxy" | "catch") => params_line_style(childs),
+        Some("if" | "if-not" | "when" | "when-not" | "while") => PrintStyle::Block(1),
+        Some("do" | "try" | "finally" | "comment") => PrintStyle::Block(0),
+        Some("cond") => PrintStyle::PairsBody(0),
+        Some("case" | "cond->" | "cond->>") => PrintStyle::PairsBody(1),
+        Some("condp") => PrintStyle::PairsBody(2),
+        _ => PrintStyle::Standard,
+    }
+}
+
+// Prints the form in one line
 pub fn lisp_form_print_tokens(pform: &PrintableLispForm) -> Vec<PrintToken> {
-    flatten_print_tokens(&lisp_form_print_tokens_aux(pform, 0))
+    render(&form_doc(pform, indent_rules()), usize::MAX, Mode::Flat)
+}
+
+// Lays out the form in lines of at most width columns, as long as its atoms allow it
+pub fn style_lisp_form(pform: &PrintableLispForm, width: usize) -> Vec<PrintToken> {
+    style_lisp_form_with_rules(pform, width, indent_rules())
+}
+
+fn style_lisp_form_with_rules(
+    pform: &PrintableLispForm,
+    width: usize,
+    rules: &IndentRules,
+) -> Vec<PrintToken> {
+    render(&form_doc(pform, rules), width, Mode::Break)
 }
 
-fn symb_style_lisp_form_deep(pform: &mut PrintableLispForm) {
+////////////
+// Values //
+////////////
+
+fn flat_width(doc: &Doc) -> usize {
+    render(doc, usize::MAX, Mode::Flat)
+        .iter()
+        .map(token_width)
+        .sum()
+}
+
+fn is_map_like(pform: &PrintableLispForm) -> bool {
+    matches!(
+        pform,
+        PrintableLispForm::Map { .. }
+            | PrintableLispForm::NamespacedMap { .. }
+            | PrintableLispForm::Truncated(_)
+    )
+}
+
+// All the entries in one line when they fit, otherwise one per line with the vals
+// aligned after the widest key
+fn aligned_pairs_doc(
+    keys: &[PrintableLispForm],
+    vals: &[PrintableLispForm],
+    truncated: Option<Truncation>,
+) -> Doc {
+    let keys = keys.iter().map(value_doc).collect::<Vec<Doc>>();
+    let vals = vals.iter().map(value_doc).collect::<Vec<Doc>>();
+    let key_widths = keys.iter().map(flat_width).collect::<Vec<usize>>();
+    let key_width = key_widths.iter().copied().max().unwrap_or(0);
+    let truncated = truncated.map(|t| value_doc(&PrintableLispForm::Truncated(t)));
+
+    let flat_pairs = keys
+        .iter()
+        .zip(&vals)
+        .map(|(k, v)| Doc::Concat(vec![k.clone(), text(PrintToken::Space), v.clone()]))
+        .chain(truncated.clone())
+        .collect::<Vec<Doc>>();
+    let aligned_pairs = keys
+        .into_iter()
+        .zip(key_widths)
+        .zip(vals)
+        .map(|((k, w), v)| {
+            let mut padded = vec![flat(k.clone())];
+            padded.extend(std::iter::repeat_n(
+                text(PrintToken::Space),
+                key_width - w + 1,
+            ));
+            padded.push(v.clone());
+            choice(
+                Doc::Concat(padded),
+                Doc::Concat(vec![k, nest(INDENT_WIDTH, Doc::Concat(vec![Doc::Line, v]))]),
+            )
+        })
+        .chain(truncated);
+
+    choice(
+        flat(join(flat_pairs, &Doc::Line)),
+        align(join(aligned_pairs, &Doc::Line)),
+    )
+}
+
+// Values are data, so their lists aren't calls and get no code styles. Vectors of
+// maps, like query results, go one map per line.
+fn value_doc(pform: &PrintableLispForm) -> Doc {
+    let docs = |childs: &[PrintableLispForm]| childs.iter().map(value_doc).collect::<Vec<Doc>>();
     match pform {
-        PrintableLispForm::List {
-            childs,
-            ref mut style,
-            coord: _,
-        } => {
-            if let Some(e) = childs.as_slice().first() {
-                if let PrintableLispForm::Atomic(s, _) = e {
-                    *style = match s.as_ref() {
-                        "defn" => PrintStyle::Defn,
-                        "let" => PrintStyle::Binding,
-                        "binding" => PrintStyle::Binding,
-                        _ => PrintStyle::Unstyled,
-                    }
-                }
-            }
-            for c in childs {
-                symb_style_lisp_form_deep(c)
-            }
-        }
-        PrintableLispForm::Vector {
-            childs,
-            style: _,
-            coord: _,
-        } => {
-            for c in childs {
-                symb_style_lisp_form_deep(c)
-            }
-        }
-        PrintableLispForm::Set {
-            childs,
-            style: _,
-            coord: _,
-        } => {
-            for c in childs {
-                symb_style_lisp_form_deep(c)
-            }
-        }
         PrintableLispForm::Map {
             keys,
             vals,
-            style: _,
-            coord: _,
-        } => {
-            for c in keys {
-                symb_style_lisp_form_deep(c)
-            }
-            for c in vals {
-                symb_style_lisp_form_deep(c)
-            }
+            truncated,
+            coord,
+            ..
+        } => delimited_doc("{", "}", coord, aligned_pairs_doc(keys, vals, *truncated)),
+
+        PrintableLispForm::Vector { childs, coord, .. }
+            if childs.len() > 1 && childs.iter().all(is_map_like) =>
+        {
+            delimited_doc("[", "]", coord, align(join(docs(childs), &Doc::Line)))
         }
-        PrintableLispForm::Tagged {
-            tag: _,
-            form,
-            coord: _,
-        } => symb_style_lisp_form_deep(form),
-        PrintableLispForm::String(_) => (),
-        PrintableLispForm::Regexp(_) => (),
-        PrintableLispForm::Atomic(_, _) => (),
+
+        PrintableLispForm::Vector { childs, coord, .. } => {
+            delimited_doc("[", "]", coord, align(fill(docs(childs))))
+        }
+
+        PrintableLispForm::List { childs, coord, .. } => {
+            delimited_doc("(", ")", coord, align(fill(docs(childs))))
+        }
+
+        PrintableLispForm::Set { childs, coord, .. } => {
+            delimited_doc("#{", "}", coord, align(fill(docs(childs))))
+        }
+
+        PrintableLispForm::NamespacedMap { ns, map } => Doc::Concat(vec![
+            text(PrintToken::Atomic {
+                val: format!("#:{}", ns),
+                coord: form_coord(map),
+                kind: None,
+            }),
+            value_doc(map),
+        ]),
+
+        PrintableLispForm::Tagged { tag, form, coord } => Doc::Concat(vec![
+            text(PrintToken::Atomic {
+                val: format!("#{}", tag),
+                coord: coord.to_vec(),
+                kind: None,
+            }),
+            text(PrintToken::Space),
+            value_doc(form),
+        ]),
+
+        PrintableLispForm::Meta { meta, form } => Doc::Concat(vec![
+            text(PrintToken::Atomic {
+                val: "^".to_string(),
+                coord: form_coord(meta),
+                kind: None,
+            }),
+            value_doc(meta),
+            text(PrintToken::Space),
+            value_doc(form),
+        ]),
+
+        _ => form_doc(pform, indent_rules()),
     }
 }
 
-fn standard_style_next_unstyled_childs(childs: &mut Vec<PrintableLispForm>) -> bool {
-    childs
-        .iter_mut()
-        .map(|c| standard_style_next_unstyled(c))
-        .fold(false, |acc, n| acc || n)
+// Lays out a value, like a fn result or a local, in lines of at most width columns
+pub fn style_lisp_value(pform: &PrintableLispForm, width: usize) -> Vec<PrintToken> {
+    render(&value_doc(pform), width, Mode::Break)
 }
 
-fn standard_style_next_unstyled(pform: &mut PrintableLispForm) -> bool {
-    match pform {
-        PrintableLispForm::List {
-            ref mut childs,
-            ref mut style,
-            coord: _,
-        } => {
-            if let PrintStyle::Unstyled = style {
-                *style = PrintStyle::Standard;
-                return true;
-            } else {
-                return standard_style_next_unstyled_childs(childs);
-            }
-        }
-        PrintableLispForm::Vector {
-            ref mut childs,
-            style: _,
-            coord: _,
-        } => standard_style_next_unstyled_childs(childs),
-        PrintableLispForm::Set {
-            ref mut childs,
-            style: _,
-            coord: _,
-        } => standard_style_next_unstyled_childs(childs),
-        PrintableLispForm::Map {
-            ref mut keys,
-            ref mut vals,
-            style: _,
-            coord: _,
-        } => standard_style_next_unstyled_childs(keys) || standard_style_next_unstyled_childs(vals),
-        PrintableLispForm::Tagged {
-            tag: _,
-            ref mut form,
-            coord: _,
-        } => standard_style_next_unstyled(&mut *form),
-        PrintableLispForm::String(_) => false,
-        PrintableLispForm::Regexp(_) => false,
-        PrintableLispForm::Atomic(_, _) => false,
-    }
-}
-
-pub fn style_lisp_form(pform: &mut PrintableLispForm, width: usize) -> Vec<PrintToken> {
-    symb_style_lisp_form_deep(pform);
-    loop {
-        let curr_toks = lisp_form_print_tokens(pform);
-        let curr_width = tokens_width(&curr_toks);
+/////////////
+// Sources //
+/////////////
 
-        if curr_width < width {
-            return curr_toks;
-        } else {
-            let shorter = standard_style_next_unstyled(pform);
-            if !shorter {
-                return curr_toks;
+// Whitespace, comments and anything else between the forms
+fn gap_print_tokens(gap: &[char], r: &mut Vec<PrintToken>) {
+    let mut i = 0;
+    while i < gap.len() {
+        let rest = &gap[i..];
+        let len = match rest[0] {
+            '\n' => {
+                r.push(PrintToken::Newline);
+                1
             }
-        }
+            '\r' => 1,
+            c if c.is_whitespace() => {
+                r.push(PrintToken::Space);
+                1
+            }
+            ';' => {
+                let len = rest.iter().take_while(|c| **c != '\n').count();
+                r.push(PrintToken::Comment(rest[..len].iter().collect()));
+                len
+            }
+            _ => {
+                let len = rest
+                    .iter()
+                    .take_while(|c| !c.is_whitespace() && **c != ';')
+                    .count();
+                r.push(PrintToken::Text(rest[..len].iter().collect()));
+                len
+            }
+        };
+        i += len;
     }
 }
 
-#[allow(dead_code)]
-pub fn print_tokens_to_str(tokens: &Vec<PrintToken>) -> String {
-    let mut r = String::new();
-
-    for t in tokens {
-        match t {
-            PrintToken::String(s) => r.push_str(&format!("\"{}\"", s)),
-            PrintToken::Regexp(exp) => r.push_str(&format!("#\"{}\"", exp)),
-            PrintToken::BlockOpen { val, coord: _ } => r.push_str(&val),
-            PrintToken::BlockClose { val, coord: _ } => r.push_str(&val),
-            PrintToken::Atomic { val, coord: _ } => r.push_str(&val),
-            PrintToken::Space => r.push_str(" "),
-            PrintToken::Newline => r.push_str("\n"),
-            PrintToken::PrintTokensVec(_) => {
-                panic!("all print tokens should be flatten at this stage")
+// Prints source[from..to], where spans are the ones inside sorted by start with the
+// outer ones first
+fn spans_print_tokens(
+    source: &[char],
+    spans: &[&SourceSpan],
+    from: usize,
+    to: usize,
+    r: &mut Vec<PrintToken>,
+) {
+    let text = |from: usize, to: usize| source[from..to].iter().collect::<String>();
+    let mut pos = from;
+    let mut i = 0;
+    while i < spans.len() {
+        let span = spans[i];
+        let inner = spans[i + 1..]
+            .iter()
+            .take_while(|s| s.start < span.end)
+            .count();
+        let childs = &spans[i + 1..i + 1 + inner];
+        i += 1 + inner;
+
+        gap_print_tokens(&source[pos..span.start], r);
+        pos = span.end;
+        let coord = span.coord.clone();
+        match span.kind {
+            SpanKind::Atom => {
+                let atom = Atom::new(text(span.start, span.end));
+                r.push(PrintToken::Atomic {
+                    val: atom.text,
+                    coord,
+                    kind: Some(atom.kind),
+                })
+            }
+            SpanKind::String => r.push(PrintToken::String(text(span.start + 1, span.end - 1))),
+            SpanKind::Regexp => r.push(PrintToken::Regexp(text(span.start + 2, span.end - 1))),
+            SpanKind::Delimited { open, close } => {
+                r.push(PrintToken::BlockOpen {
+                    val: text(span.start, span.start + open),
+                    coord: coord.clone(),
+                });
+                spans_print_tokens(source, childs, span.start + open, span.end - close, r);
+                r.push(PrintToken::BlockClose {
+                    val: text(span.end - close, span.end),
+                    coord,
+                });
+            }
+            SpanKind::Prefixed(len) => {
+                r.push(PrintToken::Atomic {
+                    val: text(span.start, span.start + len),
+                    coord,
+                    kind: None,
+                });
+                spans_print_tokens(source, childs, span.start + len, span.end, r);
             }
         }
     }
+    gap_print_tokens(&source[pos..to], r);
+}
+
+// Prints the source as it is, with the coords of the spans read from it
+pub fn source_print_tokens(source: &str, spans: &[SourceSpan]) -> Vec<PrintToken> {
+    let end = source.trim_end().chars().count();
+    let source = source.chars().collect::<Vec<char>>();
+    let mut spans = spans.iter().collect::<Vec<&SourceSpan>>();
+    spans.sort_by_key(|s| (s.start, std::cmp::Reverse(s.end)));
+    let mut r = Vec::new();
+    spans_print_tokens(&source, &spans, 0, end, &mut r);
     r
 }
 
+#[allow(dead_code)]
+pub fn print_token_to_str(t: &PrintToken) -> String {
+    match t {
+        PrintToken::String(s) => format!("\"{}\"", s),
+        PrintToken::Regexp(exp) => format!("#\"{}\"", exp),
+        PrintToken::BlockOpen { val, coord: _ } => val.clone(),
+        PrintToken::BlockClose { val, coord: _ } => val.clone(),
+        PrintToken::Atomic { val, .. } => val.clone(),
+        PrintToken::Comment(c) => c.clone(),
+        PrintToken::Text(t) => t.clone(),
+        PrintToken::Space => " ".to_string(),
+        PrintToken::Newline => "\n".to_string(),
+    }
+}
+
+pub fn print_tokens_to_str(tokens: &Vec<PrintToken>) -> String {
+    tokens.iter().map(print_token_to_str).collect()
+}
+
 ///////////
 // Tests //
 ///////////
@@ -607,55 +938,50 @@ mod tests {
     use super::*;
     use crate::lisp_reader;
 
+    fn atom(val: &str) -> Doc {
+        text(PrintToken::Atomic {
+            val: val.to_string(),
+            coord: vec![],
+            kind: None,
+        })
+    }
+
     #[test]
-    fn indented_nl_test() {
-        let toks = indented_nl(3);
+    fn render_test() {
+        let doc = group(Doc::Concat(vec![
+            atom("aaa"),
+            nest(2, Doc::Concat(vec![Doc::Line, atom("bbb")])),
+            Doc::Line,
+            align(Doc::Concat(vec![atom("c"), Doc::Line, atom("d")])),
+        ]));
         assert_eq!(
-            toks,
-            vec![
-                PrintToken::Newline,
-                PrintToken::Space,
-                PrintToken::Space,
-                PrintToken::Space
-            ]
+            print_tokens_to_str(&render(&doc, 80, Mode::Break)),
+            "aaa bbb c d"
+        );
+        assert_eq!(
+            print_tokens_to_str(&render(&doc, 5, Mode::Break)),
+            "aaa\n  bbb\nc\nd"
         );
-    }
 
-    #[test]
-    fn join_test() {
+        let doc = align(fill((1..=7).map(|i| atom(&i.to_string()))));
         assert_eq!(
-            join(
-                PrintToken::Space,
-                vec![
-                    PrintToken::String("a".to_string()),
-                    PrintToken::String("b".to_string())
-                ]
-            ),
-            vec![
-                PrintToken::String("a".to_string()),
-                PrintToken::Space,
-                PrintToken::String("b".to_string())
-            ]
-        )
-    }
+            print_tokens_to_str(&render(&doc, 6, Mode::Break)),
+            "1 2 3\n4 5 6\n7"
+        );
 
-    #[test]
-    fn join_coll_test() {
+        // hard lines break every group around them
+        let doc = group(Doc::Concat(vec![
+            atom("a"),
+            Doc::Line,
+            atom("b"),
+            Doc::HardLine,
+            atom("c"),
+        ]));
         assert_eq!(
-            join_coll(
-                vec![PrintToken::Space, PrintToken::Space],
-                vec![
-                    PrintToken::String("a".to_string()),
-                    PrintToken::String("b".to_string())
-                ]
-            ),
-            vec![
-                PrintToken::String("a".to_string()),
-                PrintToken::Space,
-                PrintToken::Space,
-                PrintToken::String("b".to_string())
-            ]
-        )
+            print_tokens_to_str(&render(&doc, 80, Mode::Break)),
+            "a\nb\nc"
+        );
+        assert_eq!(print_tokens_to_str(&render(&doc, 80, Mode::Flat)), "a b c");
     }
 
     #[test]
@@ -680,18 +1006,198 @@ mod tests {
         );
     }
 
+    #[test]
+    fn reader_syntax_print_tokens_test() {
+        for (input, printed) in [
+            ("#atom[{1 2} 0x25176608]", "#atom [{1 2} 0x25176608]"),
+            ("(map #(inc %) @state)", "(map #(inc %) @state)"),
+            (
+                "^:private #?(:clj a :cljs b)",
+                "^:private #?(:clj a :cljs b)",
+            ),
+            ("(let [a {:b 1 :c 2}] a)", "(let [a {:b 1 :c 2}] a)"),
+            ("#:user{:a 'b}", "#:user{:a 'b}"),
+        ] {
+            let form = lisp_reader::read_str(input).unwrap();
+            assert_eq!(print_tokens_to_str(&lisp_form_print_tokens(&form)), printed);
+        }
+    }
+
+    #[test]
+    fn form_layouts_test() {
+        for (input, width, printed) in [
+            ("(let [a 1 b 2] a)", 80, "(let [a 1\n      b 2]\n  a)"),
+            ("{:a 1 :b 2}", 6, "{:a 1\n :b 2}"),
+            (
+                "(defn f \"doc\" [x] (cond (pos? x) :pos :else :neg))",
+                30,
+                "(defn f\n  \"doc\"\n  [x]\n  (cond\n    (pos? x) :pos\n    :else :neg))",
+            ),
+            (
+                "(case x 1 :one 2 :two :other)",
+                10,
+                "(case x\n  1 :one\n  2 :two\n  :other)",
+            ),
+            (
+                "(condp = x 1 :one :two)",
+                10,
+                "(condp = x\n  1 :one\n  :two)",
+            ),
+            (
+                "(fn [x] (when (pos? x) (println x) x))",
+                20,
+                "(fn [x]\n  (when (pos? x)\n    (println x)\n    x))",
+            ),
+            (
+                "(doseq [x xs] (println x))",
+                80,
+                "(doseq [x xs]\n  (println x))",
+            ),
+            (
+                "(-> x (assoc :a 1) (dissoc :b))",
+                20,
+                "(-> x\n    (assoc :a 1)\n    (dissoc :b))",
+            ),
+            (
+                "(ns foo.bar (:require [a.b :as b]))",
+                80,
+                "(ns foo.bar\n  (:require [a.b :as b]))",
+            ),
+            (
+                "(try (f) (catch Exception e nil) (finally (g)))",
+                30,
+                "(try\n  (f)\n  (catch Exception e nil)\n  (finally (g)))",
+            ),
+            (
+                "(defrecord P [x] Shape (area [this] x))",
+                80,
+                "(defrecord P [x]\n  Shape\n  (area [this] x))",
+            ),
+            // short control flow forms stay in one line
+            ("(if a b c)", 80, "(if a b c)"),
+            ("{:a 1 :b 2}", 80, "{:a 1 :b 2}"),
+            // bindings break the forms around them
+            (
+                "(when x (let [a 1] a))",
+                80,
+                "(when x\n  (let [a 1]\n    a))",
+            ),
+            // the closing delimiters count for the width
+            ("(f (g a b))", 10, "(f\n (g a b))"),
+            (
+                "[1 2 3 4 5 6 7 8 9 10 11 12]",
+                12,
+                "[1 2 3 4 5 6\n 7 8 9 10 11\n 12]",
+            ),
+            (
+                "{:a [1 2 3] :b {:c 4 :d 5}}",
+                16,
+                "{:a [1 2 3]\n :b {:c 4 :d 5}}",
+            ),
+        ] {
+            let form = lisp_reader::read_str(input).unwrap();
+            assert_eq!(
+                print_tokens_to_str(&style_lisp_form(&form, width)),
+                printed,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn indent_rules_layouts_test() {
+        let rules = IndentRules::from_edn(
+            "{defcomponent [[:defn]] with-tx [[:bindings]] #\"^match\" [[:pairs 1]]}",
+        )
+        .unwrap();
+        for (input, printed) in [
+            (
+                "(defcomponent button [props] (div props))",
+                "(defcomponent button [props]\n  (div props))",
+            ),
+            (
+                "(db/with-tx [tx conn] (save tx))",
+                "(db/with-tx [tx conn]\n  (save tx))",
+            ),
+            ("(match-by x :a 1 :b 2)", "(match-by x\n  :a 1\n  :b 2)"),
+            // rules that aren't definitions or bindings only apply when breaking
+            ("(match x :a 1)", "(match x :a 1)"),
+        ] {
+            let form = lisp_reader::read_str(input).unwrap();
+            assert_eq!(
+                print_tokens_to_str(&style_lisp_form_with_rules(&form, 21, &rules)),
+                printed,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn source_print_tokens_test() {
+        let source = "; the factorial\n(defn fact [n]\n  (if (zero? n) 1, ^long (* n #(dec %) @a))) ;; done\n\n";
+        let (form, spans) = lisp_reader::read_str_with_spans(source).unwrap();
+        let tokens = source_print_tokens(source, &spans);
+        assert_eq!(print_tokens_to_str(&tokens), source.trim_end());
+        assert!(tokens.contains(&PrintToken::Comment("; the factorial".to_string())));
+        assert!(tokens.contains(&PrintToken::Text(",".to_string())));
+
+        // the atoms get the same coords they get pretty printed
+        let atoms = |tokens: &[PrintToken]| {
+            tokens
+                .iter()
+                .filter_map(|t| match t {
+                    PrintToken::Atomic { val, coord, .. } => Some((val.clone(), coord.clone())),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(atoms(&tokens), atoms(&lisp_form_print_tokens(&form)));
+    }
+
+    #[test]
+    fn value_layouts_test() {
+        let cases = [
+            ("{:a 1 :bbb 2}", 40, "{:a 1 :bbb 2}"),
+            ("{:a 1 :bbb 2}", 12, "{:a   1\n :bbb 2}"),
+            (
+                "{:a {:x 1 :yy 2} :bbb [1 2]}",
+                16,
+                "{:a   {:x  1\n       :yy 2}\n :bbb [1 2]}",
+            ),
+            ("[{:a 1} {:b 2}]", 40, "[{:a 1} {:b 2}]"),
+            ("[{:a 1} {:b 2}]", 10, "[{:a 1}\n {:b 2}]"),
+            ("[1 {:b 2}]", 8, "[1\n {:b 2}]"),
+            // lists in values are data, not calls
+            ("(defn f [a] a)", 40, "(defn f [a] a)"),
+            ("(let a b c d)", 8, "(let a b\n c d)"),
+            ("#:user{:a 1 :bb 2}", 13, "#:user{:a  1\n       :bb 2}"),
+        ];
+        for (value, width, expected) in cases {
+            let form = lisp_reader::read_str(value).unwrap();
+            assert_eq!(
+                print_tokens_to_str(&style_lisp_value(&form, width)),
+                expected,
+                "{} at width {}",
+                value,
+                width
+            );
+        }
+    }
+
     #[test]
     fn style_lisp_form_test() {
-        let mut form1 = lisp_reader::read_str(
+        let form1 = lisp_reader::read_str(
             "(defn factorial [n] (if (zero? n) 1 (* n (factorial (dec n)))))",
         )
         .unwrap();
-        let form1_toks = style_lisp_form(&mut form1, 40);
+        let form1_toks = style_lisp_form(&form1, 40);
         println!("{:?}", form1_toks);
 
         assert_eq!(
             print_tokens_to_str(&form1_toks),
-            "(defn factorial [n] \n  (if (zero? n)\n      1\n      (* n (factorial (dec n)))))"
+            "(defn factorial [n]\n  (if (zero? n)\n    1\n    (* n (factorial (dec n)))))"
         );
 
         // let mut form2 = read_str("(defn styled-ast-tokens-seq [{:keys [ptype childs-vec]} left] (cond (or (= :linear ptype) (nil? ptype)) (join :space (mapv (fn [x] (styled-ast-tokens x left)) childs-vec)) (= :standard-style ptype) (let [flen (count (first (styled-ast-tokens (first childs-vec) left)))] (->> childs-vec (map-indexed (fn [i s] (case i 0 (styled-ast-tokens s left) 1 [space-sep (styled-ast-tokens s (+ left flen 1))] [(indented-nl (+ left flen 2)) (styled-ast-tokens s (+ left flen 2))]))) (into []))) (= :defn-style ptype) (let [[a b c & r] childs-vec a-toks (styled-ast-tokens a left) a-len (count (first a-toks)) b-toks (styled-ast-tokens b (+ left a-len 1)) b-len (count (first b-toks)) c-toks (styled-ast-tokens c (+ left a-len b-len 1))] [a-toks :space b-toks :space c-toks :space (mapv (fn [x] (into (indented-nl (+ left indent-width)) (styled-ast-tokens x (+ left indent-width)))) r)]) (= :pairs-block-style ptype) (->> (partition 2 childs-vec) (map (fn [[a b]] (let [a-toks (styled-ast-tokens (assoc a :ptype :linear) left) a-str (first a-toks)] [a-toks space-sep (styled-ast-tokens b (+ left (count a-str) 3))]))) (join-coll (indented-nl (+ left indent-width)))) (= :binding-style ptype) (let [[symb bvec & r] childs-vec symb-toks (styled-ast-tokens symb left) symb-str (first symb-toks) bvec-toks (styled-ast-tokens (assoc bvec :ptype :pairs-block-style) (+ left (count symb-str) 1))] [symb-toks :space bvec-toks :space (mapv (fn [x] (into (indented-nl (+ left indent-width)) (styled-ast-tokens x (+ left indent-width)))) r)])))").unwrap();
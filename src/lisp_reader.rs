@@ -1,39 +1,210 @@
+use std::fmt;
 use std::iter::Peekable;
 use std::str::Chars;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NumberKind {
+    Integer,
+    // with the N suffix, or too big for a long
+    BigInt,
+    Ratio,
+    Decimal,
+    // with the M suffix
+    BigDecimal,
+}
+
 #[derive(Debug, Clone, PartialEq)]
-pub enum PrintStyle {
-    PairsBlock,
-    Binding,
-    Defn,
-    Standard,
-    Linear,
-    Unstyled,
+pub enum AtomKind {
+    // ns is None for simple keywords and for ::auto-resolved ones
+    Keyword { ns: Option<String>, name: String },
+    Symbol { ns: Option<String>, name: String },
+    Number(NumberKind),
+    Char,
+    Nil,
+    Bool(bool),
+}
+
+// A symbol, keyword, number, char, nil or boolean, with the text it was read from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Atom {
+    pub text: String,
+    pub kind: AtomKind,
+}
+
+fn split_ns(s: &str) -> (Option<String>, String) {
+    match s.split_once('/') {
+        // the / symbol and things like clojure.core//
+        Some((ns, name)) if !ns.is_empty() && !name.is_empty() => {
+            (Some(ns.to_string()), name.to_string())
+        }
+        _ => (None, s.to_string()),
+    }
+}
+
+fn is_integer(s: &str) -> bool {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        return !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit());
+    }
+    if let Some((radix, digits)) = s.split_once(['r', 'R']) {
+        return radix.parse::<u32>().is_ok_and(|r| {
+            (2..=36).contains(&r) && !digits.is_empty() && digits.chars().all(|c| c.is_digit(r))
+        });
+    }
+    // a leading zero makes it octal
+    let digits = if s.len() > 1 {
+        s.strip_prefix('0')
+    } else {
+        None
+    };
+    match digits {
+        Some(octal) => octal.chars().all(|c| c.is_digit(8)),
+        None => !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()),
+    }
+}
+
+fn number_kind(text: &str) -> Option<NumberKind> {
+    let unsigned = text.strip_prefix(['+', '-']).unwrap_or(text);
+    if !unsigned.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+
+    if let Some(int) = unsigned.strip_suffix('N') {
+        is_integer(int).then_some(NumberKind::BigInt)
+    } else if let Some(dec) = unsigned.strip_suffix('M') {
+        dec.parse::<f64>().is_ok().then_some(NumberKind::BigDecimal)
+    } else if let Some((num, den)) = unsigned.split_once('/') {
+        (is_integer(num) && is_integer(den)).then_some(NumberKind::Ratio)
+    } else if is_integer(unsigned) {
+        match integer_value(unsigned) {
+            Some(_) => Some(NumberKind::Integer),
+            None => Some(NumberKind::BigInt),
+        }
+    } else if unsigned.chars().all(|c| c.is_ascii_digit()) {
+        // invalid octals like 08 aren't numbers
+        None
+    } else {
+        unsigned
+            .parse::<f64>()
+            .is_ok()
+            .then_some(NumberKind::Decimal)
+    }
+}
+
+// Clojure integers can be hex 0xFF, radix 2r101 or octal 017
+fn integer_value(s: &str) -> Option<i64> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some((radix, digits)) = s.split_once(['r', 'R']) {
+        i64::from_str_radix(digits, radix.parse().ok()?).ok()
+    } else if s.len() > 1 && s.starts_with('0') {
+        i64::from_str_radix(&s[1..], 8).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+impl Atom {
+    pub fn new(text: String) -> Self {
+        let kind = if let Some(kw) = text.strip_prefix(':') {
+            let (ns, name) = match kw.strip_prefix(':') {
+                Some(auto_resolved) => (None, auto_resolved.to_string()),
+                None => split_ns(kw),
+            };
+            AtomKind::Keyword { ns, name }
+        } else if text.starts_with('\\') {
+            AtomKind::Char
+        } else if text.starts_with("##") {
+            AtomKind::Number(NumberKind::Decimal)
+        } else if let Some(kind) = number_kind(&text) {
+            AtomKind::Number(kind)
+        } else {
+            match text.as_str() {
+                "nil" => AtomKind::Nil,
+                "true" => AtomKind::Bool(true),
+                "false" => AtomKind::Bool(false),
+                _ => {
+                    let (ns, name) = split_ns(&text);
+                    AtomKind::Symbol { ns, name }
+                }
+            }
+        };
+        Self { text, kind }
+    }
+
+    pub fn is_symbol(&self) -> bool {
+        matches!(self.kind, AtomKind::Symbol { .. })
+    }
+
+    #[allow(dead_code)]
+    pub fn numeric_value(&self) -> Option<f64> {
+        let kind = match self.kind {
+            AtomKind::Number(kind) => kind,
+            _ => return None,
+        };
+        let (sign, unsigned) = match self.text.strip_prefix('-') {
+            Some(unsigned) => (-1.0, unsigned),
+            None => (1.0, self.text.strip_prefix('+').unwrap_or(&self.text)),
+        };
+        let value = match kind {
+            NumberKind::Integer => integer_value(unsigned)? as f64,
+            NumberKind::BigInt => unsigned.trim_end_matches('N').parse().ok()?,
+            NumberKind::Ratio => {
+                let (num, den) = unsigned.split_once('/')?;
+                integer_value(num)? as f64 / integer_value(den)? as f64
+            }
+            NumberKind::Decimal => match unsigned {
+                "##Inf" => f64::INFINITY,
+                "##-Inf" => f64::NEG_INFINITY,
+                "##NaN" => f64::NAN,
+                _ => unsigned.parse().ok()?,
+            },
+            NumberKind::BigDecimal => unsigned.trim_end_matches('M').parse().ok()?,
+        };
+        Some(sign * value)
+    }
+
+    // Numbers are compared by value like clojure.core/==, everything else by kind and text
+    #[allow(dead_code)]
+    pub fn value_eq(&self, other: &Atom) -> bool {
+        match (self.numeric_value(), other.numeric_value()) {
+            (Some(a), Some(b)) => a == b,
+            _ => self == other,
+        }
+    }
+}
+
+// Where a value was cut off, either by the reader limits or by Clojure's printer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Truncation {
+    // a collection nested deeper than max_depth, printed as # like *print-level* does
+    Depth,
+    // the rest of a collection longer than max_length
+    Length,
+    // the ... Clojure prints for collections longer than *print-length*
+    Elided,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum PrintableLispForm {
-    Atomic(String, Vec<u16>),
+    Atomic(Atom, Vec<u16>),
     String(String),
     List {
         childs: Vec<PrintableLispForm>,
-        style: PrintStyle,
         coord: Vec<u16>,
     },
     Vector {
         childs: Vec<PrintableLispForm>,
-        style: PrintStyle,
         coord: Vec<u16>,
     },
     Set {
         childs: Vec<PrintableLispForm>,
-        style: PrintStyle,
         coord: Vec<u16>,
     },
+    // truncated is set when the map entries after the last one were cut off
     Map {
         keys: Vec<PrintableLispForm>,
         vals: Vec<PrintableLispForm>,
-        style: PrintStyle,
+        truncated: Option<Truncation>,
         coord: Vec<u16>,
     },
     Regexp(String),
@@ -42,173 +213,780 @@ pub enum PrintableLispForm {
         form: Box<PrintableLispForm>,
         coord: Vec<u16>,
     },
+    // 'x `x ~x ~@x @x #'x, which read as (quote x), (deref x), (var x), etc,
+    // so the inner form is at coord + [1]
+    ReaderMacro {
+        prefix: String,
+        form: Box<PrintableLispForm>,
+        coord: Vec<u16>,
+    },
+    // #(...) reads as (fn* [args] (...)), so the body list is at coord + [2]
+    AnonFn {
+        body: Box<PrintableLispForm>,
+        coord: Vec<u16>,
+    },
+    // ^meta form, the form keeps the coord since metadata is invisible to the tracer
+    Meta {
+        meta: Box<PrintableLispForm>,
+        form: Box<PrintableLispForm>,
+    },
+    // #?(...) and #?@(...), only the selected branch, the first :clj or :default one,
+    // gets compiled and traced
+    ReaderConditional {
+        splicing: bool,
+        keys: Vec<PrintableLispForm>,
+        vals: Vec<PrintableLispForm>,
+        selected: Option<usize>,
+        coord: Vec<u16>,
+    },
+    // #:ns{...}, ns is whatever follows #: so it is ":" or ":alias" for auto resolved ones
+    NamespacedMap {
+        ns: String,
+        map: Box<PrintableLispForm>,
+    },
+    // the last child of a cut off list, vector or set, or a whole collection cut by depth
+    Truncated(Truncation),
+    // a string longer than max_string_length, with the number of chars left out
+    TruncatedString {
+        prefix: String,
+        omitted: usize,
+    },
+}
+
+// Reading the whole value is too slow for huge results, like a whole app state, so
+// the inspector only reads up to these limits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReadLimits {
+    pub max_depth: usize,
+    // for maps this is the number of entries
+    pub max_length: usize,
+    pub max_string_length: usize,
+    // read ... as Truncation::Elided instead of a symbol, only makes sense for printed values
+    pub elisions: bool,
+}
+
+impl ReadLimits {
+    pub const UNBOUNDED: ReadLimits = ReadLimits {
+        max_depth: usize::MAX,
+        max_length: usize::MAX,
+        max_string_length: usize::MAX,
+        elisions: false,
+    };
+}
+
+// Where a form sits in the source it was read from, in chars, so the source can be
+// shown as the user wrote it with the coords of its forms
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceSpan {
+    pub coord: Vec<u16>,
+    pub start: usize,
+    pub end: usize,
+    pub kind: SpanKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpanKind {
+    Atom,
+    String,
+    Regexp,
+    // a collection, with the chars its open and close delimiters take
+    Delimited { open: usize, close: usize },
+    // a prefix like ' or #inst followed by the form it applies to
+    Prefixed(usize),
+}
+
+fn span_kind(form: &PrintableLispForm) -> Option<SpanKind> {
+    let delimited = |open| Some(SpanKind::Delimited { open, close: 1 });
+    match form {
+        PrintableLispForm::Atomic(..) => Some(SpanKind::Atom),
+        PrintableLispForm::String(_) => Some(SpanKind::String),
+        PrintableLispForm::Regexp(_) => Some(SpanKind::Regexp),
+        PrintableLispForm::List { .. }
+        | PrintableLispForm::Vector { .. }
+        | PrintableLispForm::Map { .. } => delimited(1),
+        PrintableLispForm::Set { .. } | PrintableLispForm::AnonFn { .. } => delimited(2),
+        PrintableLispForm::ReaderConditional { splicing, .. } => {
+            delimited(if *splicing { 4 } else { 3 })
+        }
+        PrintableLispForm::Meta { .. } => Some(SpanKind::Prefixed(1)),
+        PrintableLispForm::ReaderMacro { prefix, .. } => {
+            Some(SpanKind::Prefixed(prefix.chars().count()))
+        }
+        PrintableLispForm::Tagged { tag, .. } => Some(SpanKind::Prefixed(tag.chars().count() + 1)),
+        PrintableLispForm::NamespacedMap { ns, .. } => {
+            Some(SpanKind::Prefixed(ns.chars().count() + 2))
+        }
+        PrintableLispForm::Truncated(_) | PrintableLispForm::TruncatedString { .. } => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReadErrorKind {
+    EmptyInput,
+    UnexpectedEof,
+    // the collection opened with this char at the error position is never closed
+    Unclosed(char),
+    UnmatchedDelimiter(char),
+    UnterminatedString,
+    OddMapForms,
+    InvalidReaderConditional,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadError {
+    pub kind: ReadErrorKind,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match &self.kind {
+            ReadErrorKind::EmptyInput => "Nothing to read".to_string(),
+            ReadErrorKind::UnexpectedEof => "Unexpected end of input".to_string(),
+            ReadErrorKind::Unclosed(c) => format!("Unclosed {}", c),
+            ReadErrorKind::UnmatchedDelimiter(c) => format!("Unmatched delimiter {}", c),
+            ReadErrorKind::UnterminatedString => "Unterminated string".to_string(),
+            ReadErrorKind::OddMapForms => "Map literal with an odd number of forms".to_string(),
+            ReadErrorKind::InvalidReaderConditional => {
+                "Reader conditional body must be a list".to_string()
+            }
+        };
+        write!(f, "{} at line {}, column {}", msg, self.line, self.col)
+    }
+}
+
+// The input chars, keeping track of the position for error reporting
+struct Reader<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    col: usize,
+    limits: ReadLimits,
+    // how many collections we are currently in
+    depth: usize,
+    // chars read so far
+    offset: usize,
+    // only recorded when reading sources
+    spans: Option<Vec<SourceSpan>>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(input: &'a str, limits: ReadLimits) -> Self {
+        Self {
+            chars: input.chars().peekable(),
+            line: 1,
+            col: 1,
+            limits,
+            depth: 0,
+            offset: 0,
+            spans: None,
+        }
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+
+    fn pos(&self) -> (usize, usize) {
+        (self.line, self.col)
+    }
+
+    fn error_at(&self, kind: ReadErrorKind, (line, col): (usize, usize)) -> ReadError {
+        ReadError { kind, line, col }
+    }
+
+    fn error(&self, kind: ReadErrorKind) -> ReadError {
+        self.error_at(kind, self.pos())
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.offset += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+}
+
+type ReadResult<T> = Result<T, ReadError>;
+
+// Forms the tracer never sees, like metadata or the branches of reader conditionals
+// for other platforms, are read under this index so they never show as hot
+const UNTRACED_COORD_IDX: u16 = u16::MAX;
+
+fn untraced_coord(curr_coord: &[u16]) -> Vec<u16> {
+    let mut coord = curr_coord.to_vec();
+    coord.push(UNTRACED_COORD_IDX);
+    coord
+}
+
+// Commas are whitespace too, and \r\n line endings are just two whitespace chars
+fn is_whitespace(c: char) -> bool {
+    c.is_whitespace() || c == ','
+}
+
+// Chars that end a symbol, keyword or number, the same terminating macro chars Clojure uses
+fn is_token_end(c: char) -> bool {
+    is_whitespace(c)
+        || matches!(
+            c,
+            '(' | ')' | '[' | ']' | '{' | '}' | '"' | ';' | '@' | '^' | '`' | '~' | '\\'
+        )
+}
+
+fn skip_whitespace(input: &mut Reader) {
+    while input.peek().is_some_and(|c| is_whitespace(*c)) {
+        input.next();
+    }
+}
+
+// Skips the rest of a collection, the open delim already consumed, up to and including
+// its closing delim, without reading the forms in it
+fn skip_collection_rest(
+    input: &mut Reader,
+    open: char,
+    open_pos: (usize, usize),
+) -> ReadResult<()> {
+    let mut nesting = 0;
+    loop {
+        let c = input
+            .next()
+            .ok_or_else(|| input.error_at(ReadErrorKind::Unclosed(open), open_pos))?;
+        match c {
+            '(' | '[' | '{' => nesting += 1,
+            ')' | ']' | '}' if nesting == 0 => return Ok(()),
+            ')' | ']' | '}' => nesting -= 1,
+            '"' => loop {
+                match input.next() {
+                    Some('"') => break,
+                    Some('\\') => {
+                        input.next();
+                    }
+                    Some(_) => (),
+                    None => return Err(input.error_at(ReadErrorKind::Unclosed(open), open_pos)),
+                }
+            },
+            '\\' => {
+                input.next();
+            }
+            ';' => skip_comment(input),
+            _ => (),
+        }
+    }
+}
+
+fn skip_collection(input: &mut Reader) -> ReadResult<PrintableLispForm> {
+    let open_pos = input.pos();
+    let open = input.next().unwrap();
+    skip_collection_rest(input, open, open_pos)?;
+    Ok(PrintableLispForm::Truncated(Truncation::Depth))
 }
 
 fn read_delimited_vec(
-    input: &mut Peekable<Chars>,
+    input: &mut Reader,
     delim: char,
     curr_coord: &Vec<u16>,
-) -> Vec<PrintableLispForm> {
-    input.next(); // discard the open delim
+) -> ReadResult<Vec<PrintableLispForm>> {
+    let max_forms = input.limits.max_length;
+    read_delimited_vec_from(input, delim, curr_coord, 0, max_forms)
+}
+
+// Like read_delimited_vec but the first child coord index is first_idx instead of 0,
+// and everything after max_forms forms is skipped and replaced by a Truncated form
+fn read_delimited_vec_from(
+    input: &mut Reader,
+    delim: char,
+    curr_coord: &[u16],
+    first_idx: u16,
+    max_forms: usize,
+) -> ReadResult<Vec<PrintableLispForm>> {
+    let open_pos = input.pos();
+    let open = input.next().unwrap(); // discard the open delim
     let mut v: Vec<PrintableLispForm> = Vec::new();
-    let mut form_idx = 0;
-    while let Some(c) = input.peek() {
-        if c == &delim {
-            break;
+    let mut form_idx = first_idx;
+    input.depth += 1;
+    loop {
+        skip_whitespace(input);
+        match input.peek() {
+            None => return Err(input.error_at(ReadErrorKind::Unclosed(open), open_pos)),
+            Some(c) if c == &delim => break,
+            _ => (),
         }
 
-        let mut form_coord = curr_coord.clone();
-        form_coord.push(form_idx);
-        if let Some(form) = read(input, &form_coord) {
-            v.push(form);
-            form_idx += 1;
+        if v.len() >= max_forms {
+            skip_collection_rest(input, open, open_pos)?;
+            v.push(PrintableLispForm::Truncated(Truncation::Length));
+            input.depth -= 1;
+            return Ok(v);
         }
 
-        // TODO: this should be simpler and more general
-        // something like while space or comma skip
-        if let Some(cc) = input.peek() {
-            if cc == &',' {
-                input.next(); // discard comma
-            }
-        }
-        if let Some(cc) = input.peek() {
-            if cc == &' ' {
-                input.next(); // discard whitespace
-            }
+        let mut form_coord = curr_coord.to_vec();
+        form_coord.push(form_idx);
+        if let Some(form) = read(input, &form_coord)? {
+            // a spliced reader conditional takes one coord per spliced form, and one
+            // that selects nothing takes none
+            form_idx += match &form {
+                PrintableLispForm::ReaderConditional {
+                    splicing: true,
+                    vals,
+                    selected,
+                    ..
+                } => match selected.map(|i| &vals[i]) {
+                    Some(PrintableLispForm::Vector { childs, .. })
+                    | Some(PrintableLispForm::List { childs, .. }) => childs.len() as u16,
+                    _ => 0,
+                },
+                PrintableLispForm::ReaderConditional { selected: None, .. } => 0,
+                _ => 1,
+            };
+            v.push(form);
         }
     }
     input.next(); // discard closing delim
+    input.depth -= 1;
 
-    return v;
+    Ok(v)
 }
 
-fn read_atomic_token(input: &mut Peekable<Chars>) -> String {
+fn read_atomic_token(input: &mut Reader) -> String {
     let mut s = String::new();
 
     while let Some(c) = input.next() {
-        s.push(c.clone());
-
-        if let Some(cc) = input.peek() {
-            if cc == &' '
-                || cc == &','
-                || cc == &')'
-                || cc == &'}'
-                || cc == &']'
-                || cc == &'('
-                || cc == &'{'
-                || cc == &'['
-            {
-                break;
-            }
+        s.push(c);
+
+        if input.peek().is_some_and(|cc| is_token_end(*cc)) {
+            break;
         }
     }
 
     s
 }
 
-fn read_string(input: &mut Peekable<Chars>) -> String {
+// Returns the string, up to max_len chars, and how many chars were left out
+fn read_string(input: &mut Reader, max_len: usize) -> ReadResult<(String, usize)> {
+    let start_pos = input.pos();
+    let unterminated =
+        |input: &Reader| input.error_at(ReadErrorKind::UnterminatedString, start_pos);
     let mut s = String::new();
+    let mut len = 0;
+    let mut omitted = 0;
     input.next(); // discard first "
-    while let Some(c) = input.next() {
+    loop {
+        let c = input.next().ok_or_else(|| unterminated(input))?;
         if c == '"' {
             break;
         }
-        s.push(c.clone());
+        let mut chars = vec![c];
 
         if c == '\\' {
             // when scaping skip the next char
-            let cc = input.next().unwrap();
-            s.push(cc.clone());
+            let cc = input.next().ok_or_else(|| unterminated(input))?;
+            chars.push(cc);
+        }
+
+        if len < max_len {
+            s.extend(chars);
+            len += 1;
+        } else {
+            omitted += 1;
         }
     }
 
-    s
+    Ok((s, omitted))
 }
 
-fn read_map(
-    input: &mut Peekable<Chars>,
-    curr_coord: &Vec<u16>,
-) -> (Vec<PrintableLispForm>, Vec<PrintableLispForm>) {
-    // TODO: finish this
-    let forms: Vec<PrintableLispForm> = read_delimited_vec(input, '}', curr_coord);
+fn read_map(input: &mut Reader, curr_coord: &[u16]) -> ReadResult<PrintableLispForm> {
+    let start_pos = input.pos();
+    let max_forms = input.limits.max_length.saturating_mul(2);
+    let mut forms: Vec<PrintableLispForm> =
+        read_delimited_vec_from(input, '}', curr_coord, 0, max_forms)?;
+    // a truncated map ends with a lone Truncated form where the next key should be
+    let truncated = match forms.last() {
+        Some(PrintableLispForm::Truncated(t)) if !forms.len().is_multiple_of(2) => Some(*t),
+        _ => None,
+    };
+    if truncated.is_some() {
+        forms.pop();
+    }
+    if !forms.len().is_multiple_of(2) {
+        return Err(input.error_at(ReadErrorKind::OddMapForms, start_pos));
+    }
+
     let mut keys: Vec<PrintableLispForm> = Vec::new();
     let mut vals: Vec<PrintableLispForm> = Vec::new();
-    let mut i = 0;
+    let mut forms_iter = forms.into_iter();
+    while let (Some(key), Some(val)) = (forms_iter.next(), forms_iter.next()) {
+        keys.push(key);
+        vals.push(val);
+    }
+    Ok(PrintableLispForm::Map {
+        keys,
+        vals,
+        truncated,
+        coord: curr_coord.to_vec(),
+    })
+}
 
-    while i < (forms.len()) {
-        if let Some(key) = forms.get(i) {
-            if let Some(val) = forms.get(i + 1) {
-                keys.push(key.clone());
-                vals.push(val.clone());
+fn read_char(input: &mut Reader) -> ReadResult<String> {
+    let mut s = String::new();
+    s.push(input.next().unwrap()); // the backslash
+    let c = input
+        .next()
+        .ok_or_else(|| input.error(ReadErrorKind::UnexpectedEof))?;
+    s.push(c);
+    // named chars like \space or \u00e9, anything else is a single char
+    if c.is_alphanumeric() {
+        while let Some(cc) = input.peek() {
+            if !cc.is_alphanumeric() {
+                break;
             }
+            s.push(*cc);
+            input.next();
         }
-        i = i + 2;
     }
-    (keys, vals)
+    Ok(s)
 }
 
-// TODO: this should return a Result instead of a Option
-fn read(input: &mut Peekable<Chars>, curr_coord: &Vec<u16>) -> Option<PrintableLispForm> {
-    if let Some(c) = input.peek() {
-        match c {
-            '"' => Some(PrintableLispForm::String(read_string(input))),
-            '(' => Some(PrintableLispForm::List {
-                childs: read_delimited_vec(input, ')', curr_coord),
-                style: PrintStyle::Unstyled,
+fn skip_comment(input: &mut Reader) {
+    for c in input.by_ref() {
+        if c == '\n' {
+            break;
+        }
+    }
+}
+
+// Reads the next form, skipping comments and discarded forms in between
+fn read_form(input: &mut Reader, curr_coord: &Vec<u16>) -> ReadResult<PrintableLispForm> {
+    loop {
+        skip_whitespace(input);
+        if input.peek().is_none() {
+            return Err(input.error(ReadErrorKind::UnexpectedEof));
+        }
+        if let Some(form) = read(input, curr_coord)? {
+            return Ok(form);
+        }
+    }
+}
+
+fn read_reader_macro(
+    input: &mut Reader,
+    prefix: &str,
+    curr_coord: &[u16],
+) -> ReadResult<Option<PrintableLispForm>> {
+    let mut form_coord = curr_coord.to_vec();
+    form_coord.push(1);
+    let form = read_form(input, &form_coord)?;
+    Ok(Some(PrintableLispForm::ReaderMacro {
+        prefix: prefix.to_string(),
+        form: Box::new(form),
+        coord: curr_coord.to_vec(),
+    }))
+}
+
+// The spliced forms take their coords in the parent collection, starting at curr_coord
+fn read_spliced(input: &mut Reader, curr_coord: &[u16]) -> ReadResult<PrintableLispForm> {
+    let (parent_coord, first_idx) = match curr_coord.split_last() {
+        Some((idx, parent)) => (parent.to_vec(), *idx),
+        None => return read_form(input, &untraced_coord(curr_coord)),
+    };
+    let coord = untraced_coord(curr_coord);
+    let max_forms = input.limits.max_length;
+    match input.peek() {
+        Some('[') => Ok(PrintableLispForm::Vector {
+            childs: read_delimited_vec_from(input, ']', &parent_coord, first_idx, max_forms)?,
+            coord,
+        }),
+        Some('(') => Ok(PrintableLispForm::List {
+            childs: read_delimited_vec_from(input, ')', &parent_coord, first_idx, max_forms)?,
+            coord,
+        }),
+        _ => read_form(input, &coord),
+    }
+}
+
+fn read_reader_conditional(
+    input: &mut Reader,
+    splicing: bool,
+    curr_coord: &Vec<u16>,
+) -> ReadResult<Option<PrintableLispForm>> {
+    let open_pos = input.pos();
+    if input.next() != Some('(') {
+        return Err(input.error_at(ReadErrorKind::InvalidReaderConditional, open_pos));
+    }
+    let mut keys: Vec<PrintableLispForm> = Vec::new();
+    let mut vals: Vec<PrintableLispForm> = Vec::new();
+    let mut selected = None;
+    loop {
+        skip_whitespace(input);
+        match input.peek() {
+            None => return Err(input.error_at(ReadErrorKind::Unclosed('('), open_pos)),
+            Some(')') => {
+                input.next();
+                break;
+            }
+            _ => (),
+        }
+
+        let key = match read(input, &untraced_coord(curr_coord))? {
+            Some(key) => key,
+            None => continue,
+        };
+
+        let is_selected = selected.is_none()
+            && matches!(&key, PrintableLispForm::Atomic(k, _) if k.text == ":clj" || k.text == ":default");
+        let val = if is_selected && splicing {
+            skip_whitespace(input);
+            read_spliced(input, curr_coord)?
+        } else if is_selected {
+            read_form(input, curr_coord)?
+        } else {
+            read_form(input, &untraced_coord(curr_coord))?
+        };
+
+        if is_selected {
+            selected = Some(vals.len());
+        }
+        keys.push(key);
+        vals.push(val);
+    }
+
+    Ok(Some(PrintableLispForm::ReaderConditional {
+        splicing,
+        keys,
+        vals,
+        selected,
+        coord: curr_coord.clone(),
+    }))
+}
+
+fn read_dispatch(
+    input: &mut Reader,
+    curr_coord: &Vec<u16>,
+) -> ReadResult<Option<PrintableLispForm>> {
+    input.next(); // discard the #
+    let next_ch = match input.peek() {
+        Some(c) => *c,
+        None => return Err(input.error(ReadErrorKind::UnexpectedEof)),
+    };
+    match next_ch {
+        '{' if input.depth >= input.limits.max_depth => Ok(Some(skip_collection(input)?)),
+        '{' => {
+            // it is a set
+            Ok(Some(PrintableLispForm::Set {
+                childs: read_delimited_vec(input, '}', curr_coord)?,
                 coord: curr_coord.clone(),
-            }),
-            '[' => Some(PrintableLispForm::Vector {
-                childs: read_delimited_vec(input, ']', curr_coord),
-                style: PrintStyle::Unstyled,
+            }))
+        }
+        '"' => {
+            // its a regex
+            let (exp, _) = read_string(input, usize::MAX)?;
+            Ok(Some(PrintableLispForm::Regexp(exp)))
+        }
+        '_' => {
+            // discard the next form, it doesn't take a coord
+            input.next();
+            read_form(input, &untraced_coord(curr_coord))?;
+            Ok(None)
+        }
+        '\'' => {
+            input.next();
+            read_reader_macro(input, "#'", curr_coord)
+        }
+        '(' => {
+            let mut body_coord = curr_coord.clone();
+            body_coord.push(2);
+            let body = read_form(input, &body_coord)?;
+            Ok(Some(PrintableLispForm::AnonFn {
+                body: Box::new(body),
                 coord: curr_coord.clone(),
-            }),
-            '#' => {
+            }))
+        }
+        '?' => {
+            input.next();
+            let splicing = input.peek() == Some(&'@');
+            if splicing {
                 input.next();
-                let next_ch = input.peek().unwrap();
-                if *next_ch == '{' {
-                    // it is a set
-                    Some(PrintableLispForm::Set {
-                        childs: read_delimited_vec(input, '}', curr_coord),
-                        style: PrintStyle::Unstyled,
-                        coord: curr_coord.clone(),
-                    })
-                } else if *next_ch == '"' {
-                    // its a regex
-                    let exp = read_string(input);
-                    Some(PrintableLispForm::Regexp(exp))
-                } else {
-                    // assume it is a tagged val
-                    let tag = read_atomic_token(input);
-                    let form = read(input, curr_coord).unwrap();
-                    Some(PrintableLispForm::Tagged {
-                        tag: tag,
-                        form: Box::new(form),
-                        coord: curr_coord.clone(),
-                    })
-                }
             }
-            '{' => {
-                let (keys, vals) = read_map(input, curr_coord);
-                Some(PrintableLispForm::Map {
-                    keys: keys,
-                    vals: vals,
-                    style: PrintStyle::Unstyled,
-                    coord: curr_coord.clone(),
-                })
+            read_reader_conditional(input, splicing, curr_coord)
+        }
+        ':' => {
+            input.next();
+            let mut ns = String::new();
+            while let Some(c) = input.peek() {
+                if *c == '{' || is_whitespace(*c) {
+                    break;
+                }
+                ns.push(*c);
+                input.next();
             }
-            _ => Some(PrintableLispForm::Atomic(
-                read_atomic_token(input),
+            let map = read_form(input, curr_coord)?;
+            Ok(Some(PrintableLispForm::NamespacedMap {
+                ns,
+                map: Box::new(map),
+            }))
+        }
+        '#' => {
+            // symbolic values ##Inf ##-Inf ##NaN
+            input.next();
+            Ok(Some(PrintableLispForm::Atomic(
+                Atom::new(format!("##{}", read_atomic_token(input))),
                 curr_coord.clone(),
-            )),
+            )))
+        }
+        _ => {
+            // assume it is a tagged val
+            let tag = read_atomic_token(input);
+            let form = read_form(input, curr_coord)?;
+            Ok(Some(PrintableLispForm::Tagged {
+                tag,
+                form: Box::new(form),
+                coord: curr_coord.clone(),
+            }))
         }
-    } else {
-        None
     }
 }
 
-pub fn read_str(input: &str) -> Option<PrintableLispForm> {
-    let mut input_iter = input.chars().peekable();
-    read(&mut input_iter, &Vec::new())
+// Returns None when there is nothing to read at this point, like for comments
+// and discarded forms
+fn read(input: &mut Reader, curr_coord: &Vec<u16>) -> ReadResult<Option<PrintableLispForm>> {
+    let start = input.offset;
+    let form = read_next(input, curr_coord)?;
+    if let (Some(spans), Some(form)) = (input.spans.as_mut(), &form) {
+        if let Some(kind) = span_kind(form) {
+            // metadata is never traced, so neither is its ^
+            let coord = match form {
+                PrintableLispForm::Meta { .. } => untraced_coord(curr_coord),
+                _ => curr_coord.clone(),
+            };
+            // #( and ) are the delimiters of the body list, the last span recorded
+            if let PrintableLispForm::AnonFn { .. } = form {
+                spans.pop();
+            }
+            spans.push(SourceSpan {
+                coord,
+                start,
+                end: input.offset,
+                kind,
+            });
+        }
+    }
+    Ok(form)
+}
+
+fn read_next(input: &mut Reader, curr_coord: &Vec<u16>) -> ReadResult<Option<PrintableLispForm>> {
+    let c = match input.peek() {
+        Some(c) => *c,
+        None => return Ok(None),
+    };
+    match c {
+        '(' | '[' | '{' if input.depth >= input.limits.max_depth => {
+            Ok(Some(skip_collection(input)?))
+        }
+        '"' => {
+            let (s, omitted) = read_string(input, input.limits.max_string_length)?;
+            if omitted > 0 {
+                Ok(Some(PrintableLispForm::TruncatedString {
+                    prefix: s,
+                    omitted,
+                }))
+            } else {
+                Ok(Some(PrintableLispForm::String(s)))
+            }
+        }
+        '(' => Ok(Some(PrintableLispForm::List {
+            childs: read_delimited_vec(input, ')', curr_coord)?,
+            coord: curr_coord.clone(),
+        })),
+        '[' => Ok(Some(PrintableLispForm::Vector {
+            childs: read_delimited_vec(input, ']', curr_coord)?,
+            coord: curr_coord.clone(),
+        })),
+        '#' => read_dispatch(input, curr_coord),
+        '{' => Ok(Some(read_map(input, curr_coord)?)),
+        ')' | ']' | '}' => Err(input.error(ReadErrorKind::UnmatchedDelimiter(c))),
+        ';' => {
+            // comments don't take a coord
+            skip_comment(input);
+            Ok(None)
+        }
+        '\\' => Ok(Some(PrintableLispForm::Atomic(
+            Atom::new(read_char(input)?),
+            curr_coord.clone(),
+        ))),
+        '\'' | '`' | '@' => {
+            input.next();
+            read_reader_macro(input, &c.to_string(), curr_coord)
+        }
+        '~' => {
+            input.next();
+            if input.peek() == Some(&'@') {
+                input.next();
+                read_reader_macro(input, "~@", curr_coord)
+            } else {
+                read_reader_macro(input, "~", curr_coord)
+            }
+        }
+        '^' => {
+            input.next();
+            let meta = read_form(input, &untraced_coord(curr_coord))?;
+            let form = read_form(input, curr_coord)?;
+            Ok(Some(PrintableLispForm::Meta {
+                meta: Box::new(meta),
+                form: Box::new(form),
+            }))
+        }
+        _ => {
+            let token = read_atomic_token(input);
+            if input.limits.elisions && token == "..." {
+                Ok(Some(PrintableLispForm::Truncated(Truncation::Elided)))
+            } else {
+                Ok(Some(PrintableLispForm::Atomic(
+                    Atom::new(token),
+                    curr_coord.clone(),
+                )))
+            }
+        }
+    }
+}
+
+pub fn read_str(input: &str) -> Result<PrintableLispForm, ReadError> {
+    read_str_with_limits(input, ReadLimits::UNBOUNDED)
+}
+
+pub fn read_str_with_limits(
+    input: &str,
+    limits: ReadLimits,
+) -> Result<PrintableLispForm, ReadError> {
+    read_first_form(&mut Reader::new(input, limits))
+}
+
+// Reads a form from its source, with the spans of all its forms
+pub fn read_str_with_spans(input: &str) -> Result<(PrintableLispForm, Vec<SourceSpan>), ReadError> {
+    let mut reader = Reader::new(input, ReadLimits::UNBOUNDED);
+    reader.spans = Some(Vec::new());
+    let form = read_first_form(&mut reader)?;
+    Ok((form, reader.spans.unwrap_or_default()))
+}
+
+fn read_first_form(reader: &mut Reader) -> Result<PrintableLispForm, ReadError> {
+    // skip leading comments and discarded forms
+    loop {
+        skip_whitespace(reader);
+        if reader.peek().is_none() {
+            return Err(reader.error(ReadErrorKind::EmptyInput));
+        }
+        if let Some(form) = read(reader, &Vec::new())? {
+            return Ok(form);
+        }
+    }
 }
 
 fn lisp_form_vec_to_str(v: Vec<PrintableLispForm>) -> String {
@@ -223,49 +1001,66 @@ impl ToString for PrintableLispForm {
         match self {
             PrintableLispForm::String(s) => format!("\"{}\"", s),
             PrintableLispForm::Regexp(exp) => format!("#\"{}\"", exp),
-            PrintableLispForm::Atomic(s, _) => format!("{}", s),
-            PrintableLispForm::List {
-                childs,
-                style: _,
-                coord: _,
-            } => {
+            PrintableLispForm::Atomic(atom, _) => atom.text.clone(),
+            PrintableLispForm::List { childs, coord: _ } => {
                 format!("({})", lisp_form_vec_to_str(childs.to_vec()))
             }
-            PrintableLispForm::Vector {
-                childs,
-                style: _,
-                coord: _,
-            } => {
+            PrintableLispForm::Vector { childs, coord: _ } => {
                 format!("[{}]", lisp_form_vec_to_str(childs.to_vec()))
             }
-            PrintableLispForm::Set {
-                childs,
-                style: _,
-                coord: _,
-            } => {
+            PrintableLispForm::Set { childs, coord: _ } => {
                 format!("#{{{}}}", lisp_form_vec_to_str(childs.to_vec()))
             }
             PrintableLispForm::Map {
                 keys,
                 vals,
-                style: _,
+                truncated,
                 coord: _,
             } => {
-                let content = keys
+                let mut entries = keys
                     .iter()
                     .zip(vals)
                     .map(|(k, v)| format!("{} {}", k.to_string(), v.to_string()))
-                    .collect::<Vec<String>>()
-                    .join(" ");
-                format!("{{{}}}", content)
+                    .collect::<Vec<String>>();
+                if let Some(t) = truncated {
+                    entries.push(PrintableLispForm::Truncated(*t).to_string());
+                }
+                format!("{{{}}}", entries.join(" "))
             }
             PrintableLispForm::Tagged {
                 tag,
                 form,
                 coord: _,
             } => {
-                format!("#{}{}", tag, form.to_string())
+                format!("#{} {}", tag, form.to_string())
+            }
+            PrintableLispForm::ReaderMacro { prefix, form, .. } => {
+                format!("{}{}", prefix, form.to_string())
+            }
+            PrintableLispForm::AnonFn { body, .. } => format!("#{}", body.to_string()),
+            PrintableLispForm::Meta { meta, form } => {
+                format!("^{} {}", meta.to_string(), form.to_string())
             }
+            PrintableLispForm::ReaderConditional {
+                splicing,
+                keys,
+                vals,
+                ..
+            } => {
+                let content = keys
+                    .iter()
+                    .zip(vals)
+                    .map(|(k, v)| format!("{} {}", k.to_string(), v.to_string()))
+                    .collect::<Vec<String>>()
+                    .join(" ");
+                format!("#?{}({})", if *splicing { "@" } else { "" }, content)
+            }
+            PrintableLispForm::NamespacedMap { ns, map } => {
+                format!("#:{}{}", ns, map.to_string())
+            }
+            PrintableLispForm::Truncated(Truncation::Depth) => "#".to_string(),
+            PrintableLispForm::Truncated(_) => "...".to_string(),
+            PrintableLispForm::TruncatedString { prefix, .. } => format!("\"{}...\"", prefix),
         }
     }
 }
@@ -277,11 +1072,12 @@ impl ToString for PrintableLispForm {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lisp_pprinter::{lisp_form_print_tokens, print_tokens_to_str, PrintToken};
 
     #[test]
     fn read_string_test() {
         let mut r_str = String::from("");
-        if let Some(form) = read_str("\"this is a string\"") {
+        if let Ok(form) = read_str("\"this is a string\"") {
             if let PrintableLispForm::String(s) = form {
                 r_str = s;
             }
@@ -292,9 +1088,9 @@ mod tests {
     #[test]
     fn read_atomic_token_test() {
         let mut r_str = String::from("");
-        if let Some(form) = read_str("some_token") {
-            if let PrintableLispForm::Atomic(s, _) = form {
-                r_str = s;
+        if let Ok(form) = read_str("some_token") {
+            if let PrintableLispForm::Atomic(atom, _) = form {
+                r_str = atom.text;
             }
         }
         assert_eq!(r_str, String::from("some_token"));
@@ -303,13 +1099,8 @@ mod tests {
     #[test]
     fn read_delimited_vec_simple_test() {
         let mut r: Vec<PrintableLispForm> = Vec::new();
-        if let Some(form) = read_str("[1 2 3 4]") {
-            if let PrintableLispForm::Vector {
-                childs,
-                style: _,
-                coord: _,
-            } = form
-            {
+        if let Ok(form) = read_str("[1 2 3 4]") {
+            if let PrintableLispForm::Vector { childs, coord: _ } = form {
                 r = childs;
             }
         }
@@ -319,13 +1110,8 @@ mod tests {
     #[test]
     fn read_delimited_vec_nested_test() {
         let mut r: Vec<PrintableLispForm> = Vec::new();
-        if let Some(form) = read_str("[#{1 something} 8 [2 3] (hello \"world\" 5)]") {
-            if let PrintableLispForm::Vector {
-                childs,
-                style: _,
-                coord: _,
-            } = form
-            {
+        if let Ok(form) = read_str("[#{1 something} 8 [2 3] (hello \"world\" 5)]") {
+            if let PrintableLispForm::Vector { childs, coord: _ } = form {
                 r = childs;
             }
         }
@@ -336,11 +1122,11 @@ mod tests {
     fn read_str_map_test() {
         let mut rkeys: Vec<PrintableLispForm> = Vec::new();
         let mut rvals: Vec<PrintableLispForm> = Vec::new();
-        if let Some(form) = read_str("{1 2 3 4}") {
+        if let Ok(form) = read_str("{1 2 3 4}") {
             if let PrintableLispForm::Map {
                 keys,
                 vals,
-                style: _,
+                truncated: _,
                 coord: _,
             } = form
             {
@@ -355,15 +1141,10 @@ mod tests {
     #[test]
     fn read_str_code_1_test() {
         let mut r: Vec<PrintableLispForm> = Vec::new();
-        if let Some(form) =
+        if let Ok(form) =
             read_str("(defn factorial [n] (if (zero? n) 1 (* n (factorial (dec n)))))")
         {
-            if let PrintableLispForm::List {
-                childs,
-                style: _,
-                coord: _,
-            } = form
-            {
+            if let PrintableLispForm::List { childs, coord: _ } = form {
                 r = childs;
             }
         }
@@ -373,13 +1154,8 @@ mod tests {
     #[test]
     fn read_str_code_2_test() {
         let mut r: Vec<PrintableLispForm> = Vec::new();
-        if let Some(form) = read_str("(let [a [1 2 3] b {:n/a 1, :c 2}] a)") {
-            if let PrintableLispForm::List {
-                childs,
-                style: _,
-                coord: _,
-            } = form
-            {
+        if let Ok(form) = read_str("(let [a [1 2 3] b {:n/a 1, :c 2}] a)") {
+            if let PrintableLispForm::List { childs, coord: _ } = form {
                 r = childs;
             }
         }
@@ -390,7 +1166,7 @@ mod tests {
     fn to_string_test() {
         let input = "(let [a #{1 2} b [1/3 2] c (\"1\" 2)] (concat a b c))";
 
-        if let Some(form) = read_str(input) {
+        if let Ok(form) = read_str(input) {
             assert_eq!(form.to_string(), String::from(input));
         } else {
             assert!(false);
@@ -401,8 +1177,8 @@ mod tests {
     fn tagged_1_test() {
         let input = "#atom[{1 2} 0x25176608]";
 
-        if let Some(form) = read_str(input) {
-            assert_eq!(form.to_string(), String::from(input));
+        if let Ok(form) = read_str(input) {
+            assert_eq!(form.to_string(), String::from("#atom [{1 2} 0x25176608]"));
         } else {
             assert!(false);
         }
@@ -412,21 +1188,366 @@ mod tests {
     fn regex_test() {
         let input = r#"(str/split something #".*")"#;
 
-        if let Some(form) = read_str(input) {
+        if let Ok(form) = read_str(input) {
             assert_eq!(form.to_string(), String::from(input));
         } else {
             assert!(false);
         }
     }
 
+    fn atoms_coords(input: &str) -> Vec<(String, Vec<u16>)> {
+        let form = read_str(input).unwrap();
+        lisp_form_print_tokens(&form)
+            .into_iter()
+            .filter_map(|t| match t {
+                PrintToken::Atomic { val, coord, .. } => Some((val, coord)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reader_macros_test() {
+        let input = "(foo 'a @b #'c `(d ~e ~@f) #(inc %) ^:private g)";
+        assert_eq!(read_str(input).unwrap().to_string(), input);
+
+        let coords = atoms_coords(input);
+        let coord_of = |atom: &str| coords.iter().find(|(a, _)| a == atom).unwrap().1.clone();
+        assert_eq!(coord_of("@"), vec![2]);
+        assert_eq!(coord_of("b"), vec![2, 1]);
+        assert_eq!(coord_of("e"), vec![4, 1, 1, 1]);
+        assert_eq!(coord_of("inc"), vec![5, 2, 0]);
+        assert_eq!(coord_of("%"), vec![5, 2, 1]);
+        // metadata is invisible to the tracer
+        assert_eq!(coord_of("g"), vec![6]);
+        assert_eq!(coord_of(":private"), vec![6, UNTRACED_COORD_IDX]);
+    }
+
+    #[test]
+    fn reader_conditionals_test() {
+        let input = "[#?(:cljs 1 :clj (f x)) #?@(:clj [a b]) c]";
+        assert_eq!(read_str(input).unwrap().to_string(), input);
+
+        let coords = atoms_coords(input);
+        let coord_of = |atom: &str| coords.iter().find(|(a, _)| a == atom).unwrap().1.clone();
+        assert_eq!(coord_of("1"), vec![0, UNTRACED_COORD_IDX]);
+        assert_eq!(coord_of("x"), vec![0, 1]);
+        assert_eq!(coord_of("a"), vec![1]);
+        assert_eq!(coord_of("b"), vec![2]);
+        assert_eq!(coord_of("c"), vec![3]);
+
+        // a conditional that selects nothing doesn't take a coord either
+        let coords = atoms_coords("(f #?(:cljs 1) 2)");
+        let coord_of = |atom: &str| coords.iter().find(|(a, _)| a == atom).unwrap().1.clone();
+        assert_eq!(coord_of("2"), vec![1]);
+    }
+
+    #[test]
+    fn comments_and_discard_test() {
+        let input = "(a ; some comment\nb #_ (ignored) c)";
+        assert_eq!(read_str(input).unwrap().to_string(), "(a b c)");
+        assert_eq!(
+            atoms_coords(input),
+            vec![
+                ("a".to_string(), vec![0]),
+                ("b".to_string(), vec![1]),
+                ("c".to_string(), vec![2])
+            ]
+        );
+        assert_eq!(read_str(";; header\n(a)").unwrap().to_string(), "(a)");
+    }
+
+    #[test]
+    fn literals_test() {
+        for input in [
+            "[\\a \\space \\( \\u00e9 ##Inf ##-Inf ##NaN]",
+            "#:person{:name \"Bob\" :age 3}",
+            "#::{:a 1}",
+            "#inst \"2022-01-01\"",
+        ] {
+            assert_eq!(read_str(input).unwrap().to_string(), input);
+        }
+    }
+
+    #[test]
+    fn tagged_round_trip_test() {
+        for input in [
+            "#foo/bar baz",
+            "#inst \"2020\"",
+            "[#uuid \"1\" #atom [1 2]]",
+        ] {
+            let form = read_str(input).unwrap();
+            assert_eq!(form.to_string(), input);
+            assert_eq!(read_str(&form.to_string()).unwrap(), form);
+            let printed = print_tokens_to_str(&lisp_form_print_tokens(&form));
+            assert_eq!(read_str(&printed).unwrap(), form);
+        }
+    }
+
+    #[test]
+    fn whitespace_test() {
+        let source = "(defn sum\n  \"Adds them\"\n  [xs]\n\n  (reduce +\n          0 ; the init\n          xs))\n";
+        let expected = "(defn sum \"Adds them\" [xs] (reduce + 0 xs))";
+
+        assert_eq!(read_str(source).unwrap().to_string(), expected);
+        // CRLF line endings, tabs, commas and trailing spaces
+        assert_eq!(
+            read_str(&source.replace('\n', " \r\n"))
+                .unwrap()
+                .to_string(),
+            expected
+        );
+        assert_eq!(
+            read_str(&source.replace("  ", "\t")).unwrap().to_string(),
+            expected
+        );
+        assert_eq!(
+            read_str("\n  { :a 1 ,\n   :b [ 2 , 3 ] }  ")
+                .unwrap()
+                .to_string(),
+            "{:a 1 :b [2 3]}"
+        );
+
+        let coords = atoms_coords(source);
+        let coord_of = |atom: &str| coords.iter().find(|(a, _)| a == atom).unwrap().1.clone();
+        assert_eq!(coord_of("reduce"), vec![4, 0]);
+        assert_eq!(coord_of("0"), vec![4, 2]);
+        assert_eq!(coords.last().unwrap(), &("xs".to_string(), vec![4, 3]));
+    }
+
+    #[test]
+    fn pretty_printed_value_test() {
+        // values the client sends for pprinted results
+        let value = "{:user/id 1,\n :user/roles #{:admin},\n :user/tags\n [\"a\"\n  \"b\"]}";
+        assert_eq!(
+            read_str(value).unwrap().to_string(),
+            "{:user/id 1 :user/roles #{:admin} :user/tags [\"a\" \"b\"]}"
+        );
+        assert_eq!(
+            read_str("(f\n @a\n^:x b)").unwrap().to_string(),
+            "(f @a ^:x b)"
+        );
+    }
+
+    #[test]
+    fn read_errors_test() {
+        let err = |input: &str| {
+            let e = read_str(input).unwrap_err();
+            (e.kind, e.line, e.col)
+        };
+        assert_eq!(err(""), (ReadErrorKind::EmptyInput, 1, 1));
+        assert_eq!(err("(a [b c]"), (ReadErrorKind::Unclosed('('), 1, 1));
+        assert_eq!(err("[a b)"), (ReadErrorKind::UnmatchedDelimiter(')'), 1, 5));
+        assert_eq!(err("(a \"bc)"), (ReadErrorKind::UnterminatedString, 1, 4));
+        assert_eq!(err("{:a 1 :b}"), (ReadErrorKind::OddMapForms, 1, 1));
+        assert_eq!(err("(a #"), (ReadErrorKind::UnexpectedEof, 1, 5));
+        assert_eq!(
+            err("(a ; comment\n [b)"),
+            (ReadErrorKind::UnmatchedDelimiter(')'), 2, 4)
+        );
+        assert_eq!(
+            read_str("{:a}").unwrap_err().to_string(),
+            "Map literal with an odd number of forms at line 1, column 1"
+        );
+    }
+
     #[test]
     fn quoted_string_test() {
         let input = r#"(str "something \"quoted\"" "bla")"#;
 
-        if let Some(form) = read_str(input) {
+        if let Ok(form) = read_str(input) {
             assert_eq!(form.to_string(), String::from(input));
         } else {
             assert!(false);
         }
     }
+
+    #[test]
+    fn atom_kinds_test() {
+        let kind = |text: &str| Atom::new(text.to_string()).kind;
+        let ns_name = |ns: Option<&str>, name: &str| (ns.map(String::from), name.to_string());
+
+        match kind(":a.b/c") {
+            AtomKind::Keyword { ns, name } => assert_eq!((ns, name), ns_name(Some("a.b"), "c")),
+            k => panic!("{:?}", k),
+        }
+        match kind("::local") {
+            AtomKind::Keyword { ns, name } => assert_eq!((ns, name), ns_name(None, "local")),
+            k => panic!("{:?}", k),
+        }
+        match kind("clojure.core//") {
+            AtomKind::Symbol { ns, name } => {
+                assert_eq!((ns, name), ns_name(Some("clojure.core"), "/"))
+            }
+            k => panic!("{:?}", k),
+        }
+        for symb in ["/", "-", "+", "->>", "-x", "a/b", "inc'"] {
+            assert!(Atom::new(symb.to_string()).is_symbol(), "{}", symb);
+        }
+
+        let numbers = [
+            ("42", NumberKind::Integer),
+            ("-42", NumberKind::Integer),
+            ("0xFF", NumberKind::Integer),
+            ("2r101", NumberKind::Integer),
+            ("017", NumberKind::Integer),
+            ("42N", NumberKind::BigInt),
+            ("99999999999999999999", NumberKind::BigInt),
+            ("1/3", NumberKind::Ratio),
+            ("-1.5e3", NumberKind::Decimal),
+            ("##Inf", NumberKind::Decimal),
+            ("1.5M", NumberKind::BigDecimal),
+        ];
+        for (text, number_kind) in numbers {
+            assert_eq!(kind(text), AtomKind::Number(number_kind), "{}", text);
+        }
+
+        assert_eq!(kind("\\a"), AtomKind::Char);
+        assert_eq!(kind("nil"), AtomKind::Nil);
+        assert_eq!(kind("true"), AtomKind::Bool(true));
+        assert_eq!(kind("false"), AtomKind::Bool(false));
+
+        // the reader classifies atoms wherever they are
+        if let Ok(PrintableLispForm::Vector { childs, .. }) = read_str("[:k 1/2 nil]") {
+            let kinds: Vec<AtomKind> = childs
+                .into_iter()
+                .map(|c| match c {
+                    PrintableLispForm::Atomic(atom, _) => atom.kind,
+                    c => panic!("{:?}", c),
+                })
+                .collect();
+            assert_eq!(
+                kinds,
+                vec![
+                    AtomKind::Keyword {
+                        ns: None,
+                        name: "k".to_string()
+                    },
+                    AtomKind::Number(NumberKind::Ratio),
+                    AtomKind::Nil
+                ]
+            );
+        } else {
+            panic!("couldn't read vector");
+        }
+    }
+
+    #[test]
+    fn atom_value_eq_test() {
+        let atom = |text: &str| Atom::new(text.to_string());
+
+        assert_eq!(atom("0x10").numeric_value(), Some(16.0));
+        assert_eq!(atom("-1/4").numeric_value(), Some(-0.25));
+        assert_eq!(atom(":a").numeric_value(), None);
+        assert_eq!(atom("017").numeric_value(), Some(15.0));
+        assert_eq!(atom("2r101").numeric_value(), Some(5.0));
+        for invalid in ["08", "-09", "08N", "2r102"] {
+            assert!(
+                !matches!(atom(invalid).kind, AtomKind::Number(_)),
+                "{}",
+                invalid
+            );
+        }
+
+        assert!(atom("1").value_eq(&atom("1.0")));
+        assert!(atom("1/2").value_eq(&atom("0.5M")));
+        assert!(atom("10N").value_eq(&atom("2r1010")));
+        assert!(!atom("1").value_eq(&atom("2")));
+        assert!(atom(":a").value_eq(&atom(":a")));
+        assert!(!atom(":a").value_eq(&atom("a")));
+        assert!(!atom("##NaN").value_eq(&atom("##NaN")));
+    }
+
+    #[test]
+    fn read_limits_test() {
+        let limits = ReadLimits {
+            max_depth: 2,
+            max_length: 3,
+            max_string_length: 5,
+            elisions: true,
+        };
+        let read_limited = |input: &str| read_str_with_limits(input, limits).map(|f| f.to_string());
+
+        assert_eq!(
+            read_limited("[1 [2 [3 [4]] \"]\"] {:a {:b {:c 1}}}]"),
+            Ok("[1 [2 # \"]\"] {:a #}]".to_string())
+        );
+        assert_eq!(
+            read_limited("[1 2 3 4 (5 \")\" \\) ;)\n) 6]"),
+            Ok("[1 2 3 ...]".to_string())
+        );
+        assert_eq!(
+            read_limited("{:a 1 :b 2 :c 3 :d 4 :e 5}"),
+            Ok("{:a 1 :b 2 :c 3 ...}".to_string())
+        );
+        assert_eq!(
+            read_limited("[\"short\" \"a long string\"]"),
+            Ok("[\"short\" \"a lon...\"]".to_string())
+        );
+        assert_eq!(
+            read_limited("[(1 2 3 4 5 6"),
+            Err(ReadError {
+                kind: ReadErrorKind::Unclosed('('),
+                line: 1,
+                col: 2
+            })
+        );
+
+        // what Clojure prints with *print-length* 2
+        match read_str_with_limits("{:a (0 1 ...), :b 1, ...}", limits) {
+            Ok(PrintableLispForm::Map {
+                keys,
+                vals,
+                truncated,
+                ..
+            }) => {
+                assert_eq!(keys.len(), 2);
+                assert_eq!(truncated, Some(Truncation::Elided));
+                assert_eq!(vals[0].to_string(), "(0 1 ...)");
+            }
+            r => panic!("{:?}", r),
+        }
+
+        // ... is just a symbol in code
+        assert!(matches!(
+            read_str("(...)"),
+            Ok(PrintableLispForm::List { childs, .. })
+                if matches!(&childs[0], PrintableLispForm::Atomic(a, _) if a.is_symbol())
+        ));
+
+        let tokens =
+            lisp_form_print_tokens(&read_str_with_limits("{:a 1 :b 2 :c 3 :d 4}", limits).unwrap());
+        assert!(tokens.contains(&PrintToken::Atomic {
+            val: "...".to_string(),
+            coord: vec![],
+            kind: None,
+        }));
+    }
+
+    #[test]
+    fn read_spans_test() {
+        let (_, spans) = read_str_with_spans("(f 'x \"s\" #{:a}) ; c").unwrap();
+        let span_of = |coord: Vec<u16>| spans.iter().find(|s| s.coord == coord).unwrap();
+
+        assert_eq!(
+            *span_of(vec![]),
+            SourceSpan {
+                coord: vec![],
+                start: 0,
+                end: 16,
+                kind: SpanKind::Delimited { open: 1, close: 1 }
+            }
+        );
+        assert_eq!(span_of(vec![0]).kind, SpanKind::Atom);
+        assert_eq!(
+            (span_of(vec![1]).start, span_of(vec![1]).kind),
+            (3, SpanKind::Prefixed(1))
+        );
+        assert_eq!((span_of(vec![1, 1]).start, span_of(vec![1, 1]).end), (4, 5));
+        assert_eq!(span_of(vec![2]).kind, SpanKind::String);
+        assert_eq!(
+            (span_of(vec![3]).start, span_of(vec![3]).kind),
+            (10, SpanKind::Delimited { open: 2, close: 1 })
+        );
+    }
 }
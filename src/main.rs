@@ -11,10 +11,19 @@ use epi::NativeOptions;
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 
+mod chrome_trace;
+mod coverage;
+mod editor;
+mod flamegraph;
+mod indent_rules;
 mod lisp_pprinter;
 mod lisp_reader;
+mod profiler;
+mod session;
 mod state;
+mod syntax;
 mod util_types;
+mod value_parser;
 mod views;
 mod ws;
 
@@ -30,6 +39,20 @@ fn main() {
     //crate::dev_data::add_cljs_compiler_1(&debugger_state_arc);
     //crate::dev_data::fn_call(&debugger_state_arc);
 
+    if std::path::Path::new(indent_rules::INDENT_RULES_FILE).exists() {
+        match indent_rules::IndentRules::load(indent_rules::INDENT_RULES_FILE) {
+            Ok(rules) => indent_rules::set_indent_rules(rules),
+            Err(e) => println!("WARNING! {}", e),
+        }
+    }
+
+    if let Ok(template) = std::env::var(editor::EDITOR_ENV_VAR) {
+        match editor::CommandLauncher::new(template) {
+            Ok(launcher) => editor::set_editor_launcher(Box::new(launcher)),
+            Err(e) => println!("WARNING! {}", e),
+        }
+    }
+
     let (tx, _rx) = mpsc::channel();
 
     ws::start_ws_server(Arc::clone(&debugger_state_arc));
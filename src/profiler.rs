@@ -0,0 +1,211 @@
+use crate::state::{ExecTrace, Flow, FlowThread};
+use crate::util_types::CallStackTreeNode;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Per function aggregates computed from the call stack tree call and return timestamps.
+// Calls that never returned (still running, or unwound by an exception) are counted
+// but don't contribute to the timings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FnProfile {
+    pub fn_ns: String,
+    pub fn_name: String,
+    pub calls: usize,
+    pub timed_calls: usize,
+    // inclusive time, recursive calls only count their outermost frame
+    pub total_time: u64,
+    // every call duration summed, for the average per call
+    calls_time: u64,
+    pub self_time: u64,
+    pub min_time: u64,
+    pub max_time: u64,
+    pub max_recursion_depth: usize,
+}
+
+impl FnProfile {
+    fn new(fn_ns: &str, fn_name: &str) -> Self {
+        Self {
+            fn_ns: fn_ns.to_string(),
+            fn_name: fn_name.to_string(),
+            calls: 0,
+            timed_calls: 0,
+            total_time: 0,
+            calls_time: 0,
+            self_time: 0,
+            min_time: u64::MAX,
+            max_time: 0,
+            max_recursion_depth: 0,
+        }
+    }
+
+    pub fn avg_time(&self) -> u64 {
+        if self.timed_calls > 0 {
+            self.calls_time / self.timed_calls as u64
+        } else {
+            0
+        }
+    }
+
+    fn merge(&mut self, other: &FnProfile) {
+        self.calls += other.calls;
+        self.timed_calls += other.timed_calls;
+        self.total_time += other.total_time;
+        self.calls_time += other.calls_time;
+        self.self_time += other.self_time;
+        self.min_time = u64::min(self.min_time, other.min_time);
+        self.max_time = u64::max(self.max_time, other.max_time);
+        self.max_recursion_depth = usize::max(self.max_recursion_depth, other.max_recursion_depth);
+    }
+}
+
+// Returns the call duration, or None if the call never returned
+pub fn node_duration(traces: &[ExecTrace], node: &CallStackTreeNode) -> Option<u64> {
+    match (
+        &traces[node.trace_idx],
+        node.ret_trace_idx.map(|idx| &traces[idx]),
+    ) {
+        (ExecTrace::FnCallTrace(fct), Some(ExecTrace::ExprTrace(ret))) => {
+            Some(ret.timestamp.saturating_sub(fct.timestamp))
+        }
+        _ => None,
+    }
+}
+
+type ProfilesMap = HashMap<(String, String), FnProfile>;
+
+fn profile_node(
+    traces: &[ExecTrace],
+    node_pointer: &Arc<Mutex<CallStackTreeNode>>,
+    on_stack: &mut HashMap<(String, String), usize>,
+    profiles: &mut ProfilesMap,
+) {
+    let node = node_pointer.lock().unwrap();
+    let fct = match &traces[node.trace_idx] {
+        ExecTrace::FnCallTrace(fct) => fct,
+        ExecTrace::ExprTrace(_) => panic!("call_stack_tree is pointing to a non FnCallTrace"),
+    };
+    let key = (fct.fn_ns.clone(), fct.fn_name.clone());
+
+    let recursion_depth = {
+        let depth = on_stack.entry(key.clone()).or_insert(0);
+        *depth += 1;
+        *depth
+    };
+
+    let duration = node_duration(traces, &node);
+    let childs_time: u64 = node
+        .childs
+        .iter()
+        .filter_map(|c| node_duration(traces, &c.lock().unwrap()))
+        .sum();
+
+    let profile = profiles
+        .entry(key.clone())
+        .or_insert_with(|| FnProfile::new(&fct.fn_ns, &fct.fn_name));
+    profile.calls += 1;
+    profile.max_recursion_depth = usize::max(profile.max_recursion_depth, recursion_depth);
+    if let Some(d) = duration {
+        profile.timed_calls += 1;
+        if recursion_depth == 1 {
+            profile.total_time += d;
+        }
+        profile.calls_time += d;
+        profile.self_time += d.saturating_sub(childs_time);
+        profile.min_time = u64::min(profile.min_time, d);
+        profile.max_time = u64::max(profile.max_time, d);
+    }
+
+    for child in &node.childs {
+        profile_node(traces, child, on_stack, profiles);
+    }
+
+    *on_stack.get_mut(&key).unwrap() -= 1;
+}
+
+fn thread_profiles_map(thread: &FlowThread) -> ProfilesMap {
+    let mut profiles = HashMap::new();
+    if let Some(cst) = &thread.call_stack_tree {
+        profile_node(
+            &thread.execution.traces,
+            &cst.root,
+            &mut HashMap::new(),
+            &mut profiles,
+        );
+    }
+    profiles
+}
+
+fn sorted_profiles(profiles: ProfilesMap) -> Vec<FnProfile> {
+    let mut r = profiles
+        .into_values()
+        .map(|mut p| {
+            if p.timed_calls == 0 {
+                p.min_time = 0;
+            }
+            p
+        })
+        .collect::<Vec<FnProfile>>();
+    r.sort_by_key(|p| std::cmp::Reverse(p.total_time));
+    r
+}
+
+pub fn thread_profile(thread: &FlowThread) -> Vec<FnProfile> {
+    sorted_profiles(thread_profiles_map(thread))
+}
+
+pub fn flow_profile(flow: &Flow) -> Vec<FnProfile> {
+    let mut profiles: ProfilesMap = HashMap::new();
+    for thread in flow.threads.values() {
+        for (key, p) in thread_profiles_map(thread) {
+            match profiles.get_mut(&key) {
+                Some(fp) => fp.merge(&p),
+                None => {
+                    profiles.insert(key, p);
+                }
+            }
+        }
+    }
+    sorted_profiles(profiles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{ExprTrace, FnCallTrace};
+
+    #[test]
+    fn thread_profile_test() {
+        let mut thread = FlowThread::new(1);
+        let call = |fn_name: &str, timestamp| {
+            FnCallTrace::new(
+                1,
+                "user".to_string(),
+                fn_name.to_string(),
+                "[]".to_string(),
+                timestamp,
+            )
+        };
+        let ret = |timestamp| ExprTrace::new(1, "nil".to_string(), vec![], true, timestamp);
+
+        // (f (f (g)) (g))
+        thread.add_fn_call_trace(call("f", 0));
+        thread.add_fn_call_trace(call("f", 10));
+        thread.add_fn_call_trace(call("g", 12));
+        thread.add_expr_trace(ret(15));
+        thread.add_expr_trace(ret(20));
+        thread.add_fn_call_trace(call("g", 30));
+        thread.add_expr_trace(ret(40));
+        thread.add_expr_trace(ret(100));
+
+        let profile = thread_profile(&thread);
+        let f = profile.iter().find(|p| p.fn_name == "f").unwrap();
+        let g = profile.iter().find(|p| p.fn_name == "g").unwrap();
+
+        assert_eq!((f.calls, f.total_time, f.self_time), (2, 100, 87));
+        assert_eq!((f.min_time, f.max_time, f.avg_time()), (10, 100, 55));
+        assert_eq!(f.max_recursion_depth, 2);
+        assert_eq!((g.calls, g.total_time, g.self_time), (2, 13, 13));
+        assert_eq!(g.max_recursion_depth, 1);
+        assert_eq!(profile[0].fn_name, "f");
+    }
+}
@@ -0,0 +1,442 @@
+use crate::state::{
+    BindTrace, Bookmark, DebuggerState, ExecTrace, ExprTrace, FnCallTrace, Form, ThreadId,
+};
+use crate::ws::{bool_from_json_value, err_from_json_value, location_from_json_value};
+use json::JsonValue;
+use std::fs;
+
+// A session is a json file with everything we received for each flow, plus the
+// bookmarks users added on top of it. Traces are stored with the same keys the
+// websocket protocol uses, and loading a session just replays them into the state.
+
+fn coord_to_json_value(coord: &[u16]) -> JsonValue {
+    JsonValue::Array(coord.iter().map(|c| JsonValue::from(*c)).collect())
+}
+
+fn exec_trace_to_json_value(trace: &ExecTrace) -> JsonValue {
+    match trace {
+        ExecTrace::FnCallTrace(fct) => json::object! {
+            "type" => "fn-call-trace",
+            "form-id" => fct.form_id,
+            "fn-ns" => fct.fn_ns.as_str(),
+            "fn-name" => fct.fn_name.as_str(),
+            "args-vec" => fct.args_vec.as_str(),
+            "timestamp" => fct.timestamp,
+        },
+        ExecTrace::ExprTrace(et) => json::object! {
+            "type" => "exec-trace",
+            "form-id" => et.form_id,
+            "coor" => coord_to_json_value(&et.coord),
+            "result" => et.result.as_str(),
+            "outer-form?" => et.is_outer_form,
+            "timestamp" => et.timestamp,
+            "err" => et.err.as_deref(),
+        },
+    }
+}
+
+fn bind_trace_to_json_value(trace: &BindTrace) -> JsonValue {
+    json::object! {
+        "form-id" => trace.form_id,
+        "coor" => coord_to_json_value(&trace.coord),
+        "symbol" => trace.symbol.as_str(),
+        "value" => trace.value.as_str(),
+        "timestamp" => trace.timestamp,
+    }
+}
+
+fn bookmark_to_json_value(bookmark: &Bookmark) -> JsonValue {
+    json::object! {
+        "trace-idx" => bookmark.trace_idx,
+        "label" => bookmark.label.as_str(),
+        "note" => bookmark.note.as_str(),
+    }
+}
+
+pub fn session_to_json_value(state: &DebuggerState) -> JsonValue {
+    let mut flows = JsonValue::new_array();
+
+    let mut flow_ids = state.flows_ids();
+    flow_ids.sort_unstable();
+
+    for flow_id in flow_ids {
+        let flow = &state.flows[&flow_id];
+
+        let mut forms = JsonValue::new_array();
+        for form in flow.forms.iter() {
+            let _ = forms.push(json::object! {
+                "form-id" => form.form_id,
+                "ns" => form.ns.as_str(),
+                "form" => form.form_str.as_str(),
+                "source" => form.source.as_deref(),
+                "file" => form.location.as_ref().and_then(|l| l.file.as_deref()),
+                "line" => form.location.as_ref().map(|l| l.line),
+                "column" => form.location.as_ref().and_then(|l| l.column),
+                "timestamp" => form.timestamp,
+            });
+        }
+
+        let mut thread_ids = flow.thread_ids();
+        thread_ids.sort_unstable();
+
+        let mut threads = JsonValue::new_array();
+        for thread_id in thread_ids {
+            let thread = &flow.threads[&thread_id];
+            let _ = threads.push(json::object! {
+                "thread-id" => thread_id,
+                "traces" => JsonValue::Array(
+                    thread.execution.traces.iter().map(exec_trace_to_json_value).collect()
+                ),
+                "bind-traces" => JsonValue::Array(
+                    thread.bind_traces.iter().map(bind_trace_to_json_value).collect()
+                ),
+                "bookmarks" => JsonValue::Array(
+                    thread.bookmarks.iter().map(bookmark_to_json_value).collect()
+                ),
+            });
+        }
+
+        let _ = flows.push(json::object! {
+            "flow-id" => flow_id,
+            "timestamp" => flow.timestamp,
+            "forms" => forms,
+            "threads" => threads,
+        });
+    }
+
+    json::object! { "flows" => flows }
+}
+
+// Sessions are files users pick, so unlike the tracer messages a field of the
+// wrong type is an error instead of a panic
+fn bad_field(obj: &JsonValue, key: &str) -> String {
+    format!("Bad {} in session: {}", key, obj[key])
+}
+
+fn u16_field(obj: &JsonValue, key: &str) -> Result<u16, String> {
+    obj[key].as_u16().ok_or_else(|| bad_field(obj, key))
+}
+
+fn i64_field(obj: &JsonValue, key: &str) -> Result<i64, String> {
+    obj[key].as_i64().ok_or_else(|| bad_field(obj, key))
+}
+
+fn u64_field(obj: &JsonValue, key: &str) -> Result<u64, String> {
+    obj[key].as_u64().ok_or_else(|| bad_field(obj, key))
+}
+
+fn string_field(obj: &JsonValue, key: &str) -> Result<String, String> {
+    obj[key]
+        .as_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| bad_field(obj, key))
+}
+
+fn coord_field(obj: &JsonValue, key: &str) -> Result<Vec<u16>, String> {
+    if !obj[key].is_array() {
+        return Err(bad_field(obj, key));
+    }
+    obj[key]
+        .members()
+        .map(|c| c.as_u16().ok_or_else(|| bad_field(obj, key)))
+        .collect()
+}
+
+// Bookmarks the thread can't jump to are skipped, with a warning for each
+fn load_thread(
+    state: &mut DebuggerState,
+    flow_id: i64,
+    thread_obj: &JsonValue,
+    warnings: &mut Vec<String>,
+) -> Result<(), String> {
+    let thread_id: ThreadId = u16_field(thread_obj, "thread-id")?;
+
+    for t in thread_obj["traces"].members() {
+        let form_id = i64_field(t, "form-id")?;
+        let timestamp = u64_field(t, "timestamp")?;
+        match t["type"].as_str() {
+            Some("fn-call-trace") => state.add_fn_call_trace(
+                flow_id,
+                thread_id,
+                FnCallTrace::new(
+                    form_id,
+                    string_field(t, "fn-ns")?,
+                    string_field(t, "fn-name")?,
+                    string_field(t, "args-vec")?,
+                    timestamp,
+                ),
+            ),
+            Some("exec-trace") => state.add_exec_trace(
+                flow_id,
+                thread_id,
+                ExprTrace::new(
+                    form_id,
+                    string_field(t, "result")?,
+                    coord_field(t, "coor")?,
+                    bool_from_json_value(t["outer-form?"].clone()),
+                    timestamp,
+                )
+                .with_err(err_from_json_value(&t["err"])),
+            ),
+            _ => return Err(format!("Unknown trace type {}", t["type"])),
+        }
+    }
+
+    for t in thread_obj["bind-traces"].members() {
+        state.add_bind_trace(
+            flow_id,
+            thread_id,
+            BindTrace::new(
+                i64_field(t, "form-id")?,
+                string_field(t, "symbol")?,
+                string_field(t, "value")?,
+                coord_field(t, "coor")?,
+                u64_field(t, "timestamp")?,
+            ),
+        );
+    }
+
+    if let Some(thread) = state
+        .flows
+        .get_mut(&flow_id)
+        .and_then(|flow| flow.threads.get_mut(&thread_id))
+    {
+        let traces_count = thread.execution.traces.len();
+        for b in thread_obj["bookmarks"].members() {
+            // bookmarks of traces the session doesn't have would jump nowhere
+            match b["trace-idx"].as_usize() {
+                Some(trace_idx) if trace_idx < traces_count => {
+                    thread.add_bookmark(Bookmark::new(
+                        trace_idx,
+                        string_field(b, "label")?,
+                        string_field(b, "note")?,
+                    ));
+                }
+                _ => warnings.push(format!(
+                    "Dropped bookmark {} of thread {}, it has no trace to jump to",
+                    b["label"], thread_id
+                )),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Loads all flows in the session into the state, replacing flows with the same id.
+// The state is only touched when the whole session loads, returns the warnings.
+pub fn load_session_json_value(
+    state: &mut DebuggerState,
+    obj: &JsonValue,
+) -> Result<Vec<String>, String> {
+    if !obj["flows"].is_array() {
+        return Err("Not a flowstorm session file".to_string());
+    }
+
+    let mut loaded = DebuggerState::new();
+    let mut warnings = Vec::new();
+    for flow_obj in obj["flows"].members() {
+        let flow_id = i64_field(flow_obj, "flow-id")?;
+        let timestamp = u64_field(flow_obj, "timestamp")?;
+
+        for form_obj in flow_obj["forms"].members() {
+            let form_id = i64_field(form_obj, "form-id")?;
+            let mut form = Form::new(
+                form_id,
+                string_field(form_obj, "ns")?,
+                string_field(form_obj, "form")?,
+                u64_field(form_obj, "timestamp")?,
+            );
+            if let Some(source) = form_obj["source"].as_str() {
+                form = form.with_source(source.to_string());
+            }
+            if let Some(location) = location_from_json_value(form_obj) {
+                form = form.with_location(location);
+            }
+            loaded.add_flow_form(flow_id, form_id, form, timestamp);
+        }
+
+        for thread_obj in flow_obj["threads"].members() {
+            load_thread(&mut loaded, flow_id, thread_obj, &mut warnings)?;
+        }
+    }
+
+    state.flows.extend(loaded.flows);
+    if loaded.selected_flow_id.is_some() {
+        state.selected_flow_id = loaded.selected_flow_id;
+    }
+    Ok(warnings)
+}
+
+pub fn save_session(state: &DebuggerState, file_path: &str) -> Result<(), String> {
+    let content = json::stringify_pretty(session_to_json_value(state), 2);
+    fs::write(file_path, content).map_err(|e| format!("Couldn't write {}: {}", file_path, e))
+}
+
+pub fn load_session(state: &mut DebuggerState, file_path: &str) -> Result<Vec<String>, String> {
+    let content =
+        fs::read_to_string(file_path).map_err(|e| format!("Couldn't read {}: {}", file_path, e))?;
+    let obj = json::parse(&content).map_err(|e| format!("Couldn't parse {}: {}", file_path, e))?;
+    load_session_json_value(state, &obj)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::SourceLocation;
+
+    #[test]
+    fn session_round_trip_test() {
+        let mut state = DebuggerState::new();
+        state.add_flow_form(
+            1,
+            10,
+            Form::new(
+                10,
+                "user".to_string(),
+                "(defn f [a] (inc a))".to_string(),
+                5,
+            )
+            .with_source("(defn f [a]\n  (inc a)) ; inc it".to_string())
+            .with_location(SourceLocation {
+                file: Some("src/user.clj".to_string()),
+                line: 12,
+                column: None,
+            }),
+            5,
+        );
+        state.add_fn_call_trace(
+            1,
+            7,
+            FnCallTrace::new(
+                10,
+                "user".to_string(),
+                "f".to_string(),
+                "[1]".to_string(),
+                6,
+            ),
+        );
+        state.add_bind_trace(
+            1,
+            7,
+            BindTrace::new(10, "a".to_string(), "1".to_string(), vec![], 7),
+        );
+        state.add_exec_trace(1, 7, ExprTrace::new(10, "2".to_string(), vec![3], false, 8));
+        state.add_exec_trace(1, 7, ExprTrace::new(10, "2".to_string(), vec![], true, 9));
+        state
+            .flows
+            .get_mut(&1)
+            .unwrap()
+            .threads
+            .get_mut(&7)
+            .unwrap()
+            .add_bookmark(Bookmark::new(
+                2,
+                "inc".to_string(),
+                "result of the inc".to_string(),
+            ));
+
+        let session = session_to_json_value(&state);
+        let mut loaded_state = DebuggerState::new();
+        load_session_json_value(&mut loaded_state, &session).unwrap();
+
+        let thread = &loaded_state.flows[&1].threads[&7];
+        assert_eq!(
+            thread.execution.traces,
+            state.flows[&1].threads[&7].execution.traces
+        );
+        assert_eq!(thread.bind_traces.len(), 1);
+        assert_eq!(
+            thread.bookmarks,
+            vec![Bookmark::new(
+                2,
+                "inc".to_string(),
+                "result of the inc".to_string()
+            )]
+        );
+        assert_eq!(loaded_state.flows[&1].forms.get(&10).unwrap().ns, "user");
+        assert_eq!(
+            loaded_state.flows[&1]
+                .forms
+                .get(&10)
+                .unwrap()
+                .source
+                .as_deref(),
+            Some("(defn f [a]\n  (inc a)) ; inc it")
+        );
+        assert_eq!(
+            loaded_state.flows[&1].forms.get(&10).unwrap().location,
+            Some(SourceLocation {
+                file: Some("src/user.clj".to_string()),
+                line: 12,
+                column: None,
+            })
+        );
+    }
+
+    #[test]
+    fn stale_bookmark_test() {
+        let session = json::parse(
+            r#"{"flows": [{"flow-id": 1, "timestamp": 0, "forms": [{"form-id": 10, "ns": "user", "form": "(inc a)", "timestamp": 0}],
+                 "threads": [{"thread-id": 7,
+                              "traces": [{"type": "exec-trace", "form-id": 10, "coor": [],
+                                          "result": "1", "outer-form?": false,
+                                          "timestamp": 1, "err": null}],
+                              "bind-traces": [],
+                              "bookmarks": [{"trace-idx": 0, "label": "ok", "note": ""},
+                                            {"trace-idx": 5, "label": "stale", "note": ""}]}]}]}"#,
+        )
+        .unwrap();
+        let mut state = DebuggerState::new();
+        let warnings = load_session_json_value(&mut state, &session).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("stale"));
+
+        let thread = state
+            .flows
+            .get_mut(&1)
+            .unwrap()
+            .threads
+            .get_mut(&7)
+            .unwrap();
+        assert_eq!(thread.bookmarks.len(), 1);
+        assert_eq!(thread.bookmarks[0].label, "ok");
+
+        thread.jump_to(&5);
+        assert_eq!(thread.execution.curr_trace_idx, 0);
+    }
+
+    #[test]
+    fn malformed_session_test() {
+        let sessions = [
+            r#"{"flows": [{"flow-id": "one", "timestamp": 0, "forms": [], "threads": []}]}"#,
+            r#"{"flows": [{"flow-id": 1, "timestamp": 0,
+                           "forms": [{"form-id": 10, "ns": 3, "form": "(inc a)", "timestamp": 0}],
+                           "threads": []}]}"#,
+            r#"{"flows": [{"flow-id": 1, "timestamp": 0, "forms": [],
+                           "threads": [{"thread-id": 7,
+                                        "traces": [{"type": "exec-trace", "form-id": 10,
+                                                    "coor": ["x"], "result": "1",
+                                                    "timestamp": 1}]}]}]}"#,
+            r#"{"flows": [{"flow-id": 1, "timestamp": 0, "forms": [],
+                           "threads": [{"thread-id": -1}]}]}"#,
+        ];
+        for session in sessions {
+            let mut state = DebuggerState::new();
+            let form = || Form::new(10, "user".to_string(), "(dec a)".to_string(), 0);
+            state.add_flow_form(1, 10, form(), 0);
+            state.add_flow_form(2, 10, form(), 0);
+            let session = json::parse(session).unwrap();
+            assert!(
+                load_session_json_value(&mut state, &session).is_err(),
+                "{}",
+                session
+            );
+            // the live flows are left as they were
+            assert_eq!(state.flows.len(), 2);
+            assert_eq!(state.flows[&1].forms.get(&10).unwrap().form_str, "(dec a)");
+            assert!(state.flows[&1].threads.is_empty());
+            assert_eq!(state.selected_flow_id, Some(2));
+        }
+    }
+}
@@ -1,25 +1,90 @@
-use crate::lisp_pprinter::style_lisp_form;
+use crate::coverage;
+use crate::coverage::NsCoverage;
+use crate::flamegraph;
+use crate::flamegraph::{FlameRect, FlameWeight};
 use crate::lisp_pprinter::PrintToken;
-use crate::lisp_reader;
-use crate::lisp_reader::{read_str, PrintableLispForm};
-use crate::util_types::{CallStackTree, SortedForms};
+use crate::lisp_pprinter::{source_print_tokens, style_lisp_form, style_lisp_value};
+use crate::lisp_reader::{read_str, read_str_with_spans, PrintableLispForm, ReadError};
+use crate::profiler;
+use crate::profiler::FnProfile;
+use crate::util_types::{CallStackTree, LruCache, NavHistory, SortedForms, TableState};
+use crate::value_parser::{ParsedValue, ValueParser};
+use std::cell::RefCell;
 use std::collections::hash_map;
 use std::collections::HashMap;
-use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
 
 pub type FlowId = i64;
 pub type FormId = i64;
 pub type ThreadId = u16;
 pub type Coord = Vec<u16>;
 
+// How many parsed values each thread keeps around for stepping back and forth
+const VALUE_CACHE_CAPACITY: usize = 64;
+
+// Widths we keep a form laid out for, so resizing back and forth doesn't relayout
+const FORM_LAYOUTS_CAPACITY: usize = 4;
+
+// Layout widths are rounded down to multiples of this many chars, so dragging a
+// panel border only relayouts every few columns instead of on every frame
+const LAYOUT_WIDTH_STEP: usize = 8;
+
+fn layout_width(width: usize) -> usize {
+    usize::max(width - width % LAYOUT_WIDTH_STEP, LAYOUT_WIDTH_STEP)
+}
+
+// Parsed locals each thread keeps around, by trace_idx and symbol
+const LOCALS_CACHE_CAPACITY: usize = 64;
+
+// Locals values we keep laid out, by trace_idx, symbol and width
+const LOCALS_LAYOUTS_CAPACITY: usize = 32;
+
 #[derive(Debug, Clone)]
 pub struct Form {
-    pub print_tokens: Vec<PrintToken>,
+    // forms we can't read are shown as raw text, with the error
+    pub lisp_form: Result<PrintableLispForm, ReadError>,
+    // print tokens laid out for the last few widths the form was shown at
+    layouts: RefCell<LruCache<usize, Arc<Vec<PrintToken>>>>,
+    pub form_str: String,
+    // the form as the user wrote it, when the tracer sends it
+    pub source: Option<String>,
+    // the source tokens, None when there is no source or we couldn't read it
+    source_tokens: Option<Arc<Vec<PrintToken>>>,
+    // source line and column of every coord, counting from the start of the source
+    source_positions: HashMap<Coord, (usize, usize)>,
+    pub location: Option<SourceLocation>,
     pub form_id: FormId,
     pub timestamp: u64,
     pub ns: String,
 }
 
+// Where a form starts in its file, lines and columns start at 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLocation {
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: Option<usize>,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}", file, self.line),
+            None => write!(f, "line {}", self.line),
+        }
+    }
+}
+
+// How the code panel shows forms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CodeLayout {
+    // laid out by the pretty printer to fit the panel
+    Pretty,
+    // as the user wrote it, for forms with a source
+    Source,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct ExprTrace {
     pub form_id: FormId,
@@ -27,6 +92,7 @@ pub struct ExprTrace {
     pub coord: Coord,
     pub timestamp: u64,
     pub is_outer_form: bool,
+    pub err: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -53,16 +119,137 @@ pub struct BindTrace {
     pub timestamp: u64,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bookmark {
+    pub trace_idx: usize,
+    pub label: String,
+    pub note: String,
+}
+
 #[derive(Debug)]
 pub struct FlowExecution {
     pub traces: Vec<ExecTrace>,
     pub curr_trace_idx: usize,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotCoord {
+    pub hits: usize,
+    pub first_trace_idx: usize,
+    pub last_trace_idx: usize,
+    // sorted, so hits up to a step are a binary search away
+    pub trace_idxs: Vec<usize>,
+}
+
+// Which executed coords the code view highlights
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HotCoordsMode {
+    // everything that ever ran
+    Cumulative,
+    // only what ran up to the current step
+    UpToCurrent,
+    // what ran up to the current step, plus dimmed what is still going to run
+    UpToCurrentAndAfter,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum FlowTool {
     Code,
     CallStack,
+    CoordHits,
+    FnCalls,
+    Profiler,
+    Flamegraph,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoordHit {
+    pub trace_idx: usize,
+    pub timestamp: u64,
+    pub fn_args: Option<String>,
+    pub result: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FnCall {
+    pub trace_idx: usize,
+    pub args_vec: String,
+    pub result: Option<String>,
+    pub depth: usize,
+    pub duration: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct FnCallsView {
+    pub fn_ns: String,
+    pub fn_name: String,
+    pub table: TableState,
+    // filtered and sorted
+    pub calls: Vec<FnCall>,
+    computed: Option<TableKey>,
+}
+
+#[derive(Debug, Default)]
+pub struct ProfilerView {
+    pub whole_flow: bool,
+    pub table: TableState,
+    pub profile: Vec<FnProfile>,
+    // what the profile was computed from, so we only recompute when it changes
+    pub profiled: Option<(bool, usize)>,
+}
+
+#[derive(Debug)]
+pub struct FlamegraphView {
+    pub weight: FlameWeight,
+    pub export_file: String,
+    pub export_status: Option<String>,
+    pub rects: Vec<FlameRect>,
+    // what the rects were laid out from, so we only lay them out again when it changes
+    pub laid_out: Option<(FlameWeight, usize)>,
+}
+
+// What the rows of a table view were computed from, the trace count and the table
+// filter and sort, so we only compute them again when it changes
+type TableKey = (usize, String, usize, bool);
+
+// A local value laid out, None while it is read or when it couldn't be
+type LocalTokens = Option<Arc<Vec<PrintToken>>>;
+
+fn table_key(trace_count: usize, table: &TableState) -> TableKey {
+    (
+        trace_count,
+        table.filter.clone(),
+        table.sort_col,
+        table.sort_desc,
+    )
+}
+
+#[derive(Debug)]
+pub struct CoordHitsView {
+    pub form_id: FormId,
+    pub coord: Coord,
+    pub table: TableState,
+    // filtered and sorted
+    pub hits: Vec<CoordHit>,
+    computed: Option<TableKey>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ValueInspector {
+    Empty,
+    // waiting for the worker to parse the value of this trace_idx
+    Parsing(usize),
+    Ready(ParsedValue),
+    // the worker died before reading it
+    Failed(String),
+}
+
+// The inspected value laid out for a width
+#[derive(Debug, Clone)]
+struct ValueLayout {
+    value: ParsedValue,
+    width: usize,
+    tokens: Arc<Vec<PrintToken>>,
 }
 
 #[derive(Debug)]
@@ -71,19 +258,52 @@ pub struct FlowThread {
     pub execution: FlowExecution,
     pub call_stack_tree: Option<CallStackTree>,
     pub bind_traces: Vec<BindTrace>,
-    pub hot_coords: HashMap<FormId, HashSet<Coord>>,
+    pub hot_coords: HashMap<FormId, HashMap<Coord, HotCoord>>,
+    pub hot_coords_mode: HotCoordsMode,
+    pub max_coord_hits: usize,
+    pub heatmap: bool,
+    pub code_layout: CodeLayout,
     pub selected_flow_tool: FlowTool,
-    pub value_inspector: Option<PrintableLispForm>,
+    pub value_inspector: ValueInspector,
+    // parsed values by trace_idx
+    value_cache: LruCache<usize, ParsedValue>,
+    // parsed locals by trace_idx and symbol
+    locals_cache: LruCache<(usize, String), ParsedValue>,
+    // the result and locals panels show values pretty printed instead of as a tree
+    // and as raw strings
+    pub pretty_result: bool,
+    pub pretty_locals: bool,
+    result_layout: Option<ValueLayout>,
+    locals_layouts: LruCache<(usize, String, usize), LocalTokens>,
+    // started the first time we inspect a value
+    value_parser: Option<ValueParser<usize>>,
+    // started the first time we show the locals pretty printed
+    locals_parser: Option<ValueParser<(usize, String)>>,
+    // the trace_idx of the locals the worker is reading
+    locals_parsing: Option<usize>,
+    // the trace_idx of the locals the worker died reading, not requested again
+    locals_failed: Option<usize>,
+    pub nav_history: NavHistory,
+    pub bookmarks: Vec<Bookmark>,
+    pub coord_hits_view: Option<CoordHitsView>,
+    pub fn_calls_view: Option<FnCallsView>,
+    pub profiler_view: ProfilerView,
+    pub flamegraph_view: FlamegraphView,
 }
 
-#[allow(dead_code)]
 #[derive(Debug)]
 pub struct Flow {
     pub flow_id: FlowId,
     pub forms: SortedForms,
     pub threads: HashMap<ThreadId, FlowThread>,
     pub selected_thread_id: Option<ThreadId>,
-    timestamp: u64,
+    pub timestamp: u64,
+    pub export_status: Option<String>,
+    pub coverage: Vec<NsCoverage>,
+    // bumped on every form and trace added through the DebuggerState
+    generation: u64,
+    // the generation the coverage was computed at
+    covered: Option<u64>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -92,6 +312,8 @@ pub enum DebuggerTool {
     Refs,
     Taps,
     Timeline,
+    Bookmarks,
+    Coverage,
 }
 
 #[derive(Debug)]
@@ -104,15 +326,148 @@ pub struct DebuggerState {
 
 impl Form {
     pub fn new(form_id: FormId, ns: String, form_str: String, timestamp: u64) -> Self {
-        let mut form = read_str(&form_str).unwrap();
-        let tokens = style_lisp_form(&mut form, 40);
         Self {
-            print_tokens: tokens,
+            lisp_form: read_str(&form_str),
+            layouts: RefCell::new(LruCache::new(FORM_LAYOUTS_CAPACITY)),
+            form_str,
+            source: None,
+            source_tokens: None,
+            source_positions: HashMap::new(),
+            location: None,
             form_id,
             ns,
             timestamp,
         }
     }
+
+    pub fn with_source(mut self, source: String) -> Self {
+        match read_str_with_spans(&source) {
+            Ok((_, spans)) => {
+                self.source_tokens = Some(Arc::new(source_print_tokens(&source, &spans)));
+                let line_starts = source
+                    .chars()
+                    .enumerate()
+                    .filter(|(_, c)| *c == '\n')
+                    .map(|(i, _)| i)
+                    .collect::<Vec<usize>>();
+                self.source_positions = spans
+                    .into_iter()
+                    .map(|span| {
+                        let line = line_starts.partition_point(|nl| *nl < span.start);
+                        let column = match line {
+                            0 => span.start,
+                            _ => span.start - line_starts[line - 1] - 1,
+                        };
+                        (span.coord, (line, column))
+                    })
+                    .collect();
+            }
+            Err(e) => println!(
+                "WARNING! Couldn't read the source of form {}, showing it pretty printed. {}",
+                self.form_id, e
+            ),
+        }
+        self.source = Some(source);
+        self
+    }
+
+    pub fn with_location(mut self, location: SourceLocation) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    // The tokens to show the form with, the source ones fall back to pretty printing
+    // for forms without a source
+    pub fn code_tokens(&self, layout: CodeLayout, width: usize) -> Arc<Vec<PrintToken>> {
+        match (layout, &self.source_tokens) {
+            (CodeLayout::Source, Some(tokens)) => Arc::clone(tokens),
+            _ => self.print_tokens(width),
+        }
+    }
+
+    // The file line of every line of code_tokens, when we know it. Pretty printed
+    // lines take the line of their first form, which we only know with a source.
+    pub fn code_lines(&self, layout: CodeLayout, tokens: &[PrintToken]) -> Vec<Option<usize>> {
+        let first_line = match &self.location {
+            Some(location) => location.line,
+            None => return Vec::new(),
+        };
+        let rows = tokens.split(|t| matches!(t, PrintToken::Newline));
+
+        if layout == CodeLayout::Source && self.source_tokens.is_some() {
+            return rows
+                .enumerate()
+                .map(|(i, _)| Some(first_line + i))
+                .collect();
+        }
+        rows.enumerate()
+            .map(|(i, row)| match i {
+                0 => Some(first_line),
+                _ => {
+                    row.iter()
+                        .find_map(|t| match t {
+                            PrintToken::BlockOpen { coord, .. }
+                            | PrintToken::Atomic { coord, .. } => self.source_positions.get(coord),
+                            _ => None,
+                        })
+                        .map(|(l, _)| first_line + l)
+                }
+            })
+            .collect()
+    }
+
+    // Where coord is in its file. Coords we can't place without a source are at
+    // the start of the form.
+    pub fn coord_location(&self, coord: &Coord) -> Option<SourceLocation> {
+        let location = self.location.as_ref()?;
+        match self.source_positions.get(coord) {
+            Some((0, column)) => Some(SourceLocation {
+                column: Some(location.column.unwrap_or(1) + column),
+                ..location.clone()
+            }),
+            Some((line, column)) => Some(SourceLocation {
+                line: location.line + line,
+                column: Some(column + 1),
+                ..location.clone()
+            }),
+            None => Some(location.clone()),
+        }
+    }
+
+    // The form laid out to fit in width chars, forms we couldn't read have no tokens
+    pub fn print_tokens(&self, width: usize) -> Arc<Vec<PrintToken>> {
+        let width = layout_width(width);
+        let mut layouts = self.layouts.borrow_mut();
+        if let Some(tokens) = layouts.get(&width) {
+            return Arc::clone(tokens);
+        }
+
+        let tokens = Arc::new(match &self.lisp_form {
+            Ok(form) => style_lisp_form(form, width),
+            Err(_) => Vec::new(),
+        });
+        layouts.insert(width, Arc::clone(&tokens));
+        tokens
+    }
+}
+
+impl Bookmark {
+    pub fn new(trace_idx: usize, label: String, note: String) -> Self {
+        Self {
+            trace_idx,
+            label,
+            note,
+        }
+    }
+}
+
+impl ExecTrace {
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            ExecTrace::FnCallTrace(fct) => fct.timestamp,
+            ExecTrace::ExprTrace(et) => et.timestamp,
+        }
+    }
 }
 
 impl ExprTrace {
@@ -129,8 +484,15 @@ impl ExprTrace {
             coord,
             is_outer_form,
             timestamp,
+            err: None,
         }
     }
+
+    // For traces of expressions that threw instead of returning
+    pub fn with_err(mut self, err: Option<String>) -> Self {
+        self.err = err;
+        self
+    }
 }
 
 impl FnCallTrace {
@@ -179,6 +541,28 @@ fn is_coord_in_scope(scope_coord: &Coord, current_coord: &Coord) -> bool {
     }
 }
 
+// The locals in scope of the executing trace, sorted by value
+fn locals_at<'a>(
+    execution: &FlowExecution,
+    bind_traces: &'a [BindTrace],
+) -> Vec<(&'a str, &'a str)> {
+    let curr_trace = execution.executing_trace();
+    let mut bindings: HashMap<&str, &str> = HashMap::new();
+    for bt in bind_traces {
+        if let ExecTrace::ExprTrace(et) = curr_trace {
+            if bt.form_id == et.form_id
+                && bt.timestamp <= et.timestamp
+                && is_coord_in_scope(&bt.coord, &et.coord)
+            {
+                bindings.insert(bt.symbol.as_str(), bt.value.as_str());
+            }
+        }
+    }
+    let mut bindings_vec: Vec<(&str, &str)> = bindings.into_iter().collect();
+    bindings_vec.sort_by_key(|t| t.1);
+    bindings_vec
+}
+
 impl FlowExecution {
     pub fn new() -> Self {
         Self {
@@ -206,8 +590,14 @@ impl FlowExecution {
         }
     }
 
-    pub fn jump_to(&mut self, trace_idx: &usize) {
+    // Indexes out of the traces, like the ones of stale bookmarks, are ignored.
+    // Returns whether it jumped.
+    pub fn jump_to(&mut self, trace_idx: &usize) -> bool {
+        if *trace_idx >= self.traces.len() {
+            return false;
+        }
         self.curr_trace_idx = *trace_idx;
+        true
     }
 
     pub fn is_current_coord_executing(&self, form_id: FormId, coord: &[u16]) -> bool {
@@ -249,8 +639,34 @@ impl FlowThread {
             call_stack_tree: None,
             bind_traces: Vec::new(),
             hot_coords: HashMap::new(),
+            hot_coords_mode: HotCoordsMode::Cumulative,
+            max_coord_hits: 0,
+            heatmap: false,
+            code_layout: CodeLayout::Pretty,
             selected_flow_tool: FlowTool::Code,
-            value_inspector: None,
+            value_inspector: ValueInspector::Empty,
+            value_cache: LruCache::new(VALUE_CACHE_CAPACITY),
+            locals_cache: LruCache::new(LOCALS_CACHE_CAPACITY),
+            pretty_result: false,
+            pretty_locals: false,
+            result_layout: None,
+            locals_layouts: LruCache::new(LOCALS_LAYOUTS_CAPACITY),
+            value_parser: None,
+            locals_parser: None,
+            locals_parsing: None,
+            locals_failed: None,
+            nav_history: NavHistory::new(0),
+            bookmarks: Vec::new(),
+            coord_hits_view: None,
+            fn_calls_view: None,
+            profiler_view: ProfilerView::default(),
+            flamegraph_view: FlamegraphView {
+                weight: FlameWeight::Duration,
+                export_file: format!("thread-{}.folded", thread_id),
+                export_status: None,
+                rects: Vec::new(),
+                laid_out: None,
+            },
         }
     }
 
@@ -259,23 +675,30 @@ impl FlowThread {
         let coord = expr_trace.coord.clone();
 
         if expr_trace.is_outer_form {
+            let ret_trace_idx = self.execution.traces.len();
             if let Some(ref mut cst) = self.call_stack_tree {
-                cst.pop();
+                cst.pop(ret_trace_idx);
             }
         }
 
+        let trace_idx = self.execution.traces.len();
         self.execution.add_expr_trace(expr_trace);
 
-        if let hash_map::Entry::Vacant(e) = self.hot_coords.entry(form_id) {
-            // if it is the first hot coord for the form, create the set
-            let mut hot_coords = HashSet::new();
-
-            hot_coords.insert(coord);
-            e.insert(hot_coords);
-        } else {
-            // else just inssert
-            self.hot_coords.get_mut(&form_id).unwrap().insert(coord);
-        }
+        let hot_coord = self
+            .hot_coords
+            .entry(form_id)
+            .or_default()
+            .entry(coord)
+            .or_insert(HotCoord {
+                hits: 0,
+                first_trace_idx: trace_idx,
+                last_trace_idx: trace_idx,
+                trace_idxs: Vec::new(),
+            });
+        hot_coord.hits += 1;
+        hot_coord.last_trace_idx = trace_idx;
+        hot_coord.trace_idxs.push(trace_idx);
+        self.max_coord_hits = usize::max(self.max_coord_hits, hot_coord.hits);
     }
 
     pub fn add_fn_call_trace(&mut self, fn_call_trace: FnCallTrace) {
@@ -298,63 +721,437 @@ impl FlowThread {
         self.bind_traces.push(bind_trace);
     }
 
+    pub fn add_bookmark(&mut self, bookmark: Bookmark) {
+        self.bookmarks.push(bookmark);
+        self.bookmarks.sort_by_key(|b| b.trace_idx);
+    }
+
+    pub fn remove_bookmark(&mut self, idx: usize) {
+        self.bookmarks.remove(idx);
+    }
+
+    pub fn hot_coord(&self, form_id: FormId, coord: &Coord) -> Option<&HotCoord> {
+        self.hot_coords
+            .get(&form_id)
+            .and_then(|hot_map| hot_map.get(coord))
+    }
+
+    // Hot according to the selected hot_coords_mode
     pub fn is_coord_hot(&self, form_id: FormId, coord: &Coord) -> bool {
-        match self.hot_coords.get(&form_id) {
-            Some(hot_set) => hot_set.contains(coord),
-            None => false,
+        let curr_trace_idx = self.execution.curr_trace_idx;
+        self.hot_coord(form_id, coord)
+            .is_some_and(|hc| match self.hot_coords_mode {
+                HotCoordsMode::Cumulative => true,
+                HotCoordsMode::UpToCurrent | HotCoordsMode::UpToCurrentAndAfter => {
+                    hc.first_trace_idx <= curr_trace_idx
+                }
+            })
+    }
+
+    // Not executed yet at the current step, but it will
+    pub fn is_coord_pending(&self, form_id: FormId, coord: &Coord) -> bool {
+        let curr_trace_idx = self.execution.curr_trace_idx;
+        self.hot_coords_mode == HotCoordsMode::UpToCurrentAndAfter
+            && self
+                .hot_coord(form_id, coord)
+                .is_some_and(|hc| hc.first_trace_idx > curr_trace_idx)
+    }
+
+    // The hits according to the selected hot_coords_mode
+    pub fn coord_hit_count(&self, form_id: FormId, coord: &Coord) -> usize {
+        let curr_trace_idx = self.execution.curr_trace_idx;
+        self.hot_coord(form_id, coord)
+            .map_or(0, |hc| match self.hot_coords_mode {
+                HotCoordsMode::Cumulative => hc.hits,
+                HotCoordsMode::UpToCurrent | HotCoordsMode::UpToCurrentAndAfter => {
+                    hc.trace_idxs.partition_point(|idx| *idx <= curr_trace_idx)
+                }
+            })
+    }
+
+    // Where the hits fall between 0 and the thread hottest coord, on a log scale
+    // so a few very hot loops don't flatten everything else
+    pub fn heat(&self, hits: usize) -> f32 {
+        if hits == 0 {
+            0.0
+        } else {
+            (hits as f32).ln_1p() / (self.max_coord_hits as f32).ln_1p()
         }
     }
 
-    pub fn current_locals(&self) -> Vec<(&str, &str)> {
-        let curr_trace = self.execution.executing_trace();
-        let mut bindings: HashMap<&str, &str> = HashMap::new();
-        for bt in &self.bind_traces {
-            if let ExecTrace::ExprTrace(et) = curr_trace {
-                if bt.form_id == et.form_id
-                    && bt.timestamp <= et.timestamp
-                    && is_coord_in_scope(&bt.coord, &et.coord)
-                {
-                    bindings.insert(bt.symbol.as_str(), bt.value.as_str());
+    pub fn enclosing_fn_call(&self, trace_idx: usize) -> Option<&FnCallTrace> {
+        let cst = self.call_stack_tree.as_ref()?;
+        match &self.execution.traces[cst.enclosing_call(trace_idx)?] {
+            ExecTrace::FnCallTrace(fct) => Some(fct),
+            ExecTrace::ExprTrace(_) => None,
+        }
+    }
+
+    pub fn coord_hits(&self, form_id: FormId, coord: &[u16]) -> Vec<CoordHit> {
+        self.execution
+            .traces_for_coord(form_id, coord)
+            .into_iter()
+            .map(|(trace_idx, et)| CoordHit {
+                trace_idx,
+                timestamp: et.timestamp,
+                fn_args: self
+                    .enclosing_fn_call(trace_idx)
+                    .map(|fct| fct.args_vec.clone()),
+                result: et.result,
+            })
+            .collect()
+    }
+
+    pub fn show_coord_hits(&mut self, form_id: FormId, coord: &[u16]) {
+        self.coord_hits_view = Some(CoordHitsView {
+            form_id,
+            coord: coord.to_vec(),
+            table: TableState::default(),
+            hits: Vec::new(),
+            computed: None,
+        });
+        self.selected_flow_tool = FlowTool::CoordHits;
+    }
+
+    // Computes the view rows again when new traces arrived or its filter or sort
+    // changed. Returns whether it did.
+    pub fn update_coord_hits(&mut self) -> bool {
+        let trace_count = self.execution.traces.len();
+        let (form_id, coord, key) = match &self.coord_hits_view {
+            Some(view) => (
+                view.form_id,
+                view.coord.clone(),
+                table_key(trace_count, &view.table),
+            ),
+            None => return false,
+        };
+        if self.coord_hits_view.as_ref().unwrap().computed.as_ref() == Some(&key) {
+            return false;
+        }
+
+        let (_, filter, sort_col, sort_desc) = &key;
+        let mut hits = self.coord_hits(form_id, &coord);
+        if !filter.is_empty() {
+            hits.retain(|h| {
+                h.result.contains(filter) || h.fn_args.as_ref().is_some_and(|a| a.contains(filter))
+            });
+        }
+        match sort_col {
+            1 => hits.sort_by_key(|h| h.timestamp),
+            2 => hits.sort_by(|a, b| a.fn_args.cmp(&b.fn_args)),
+            3 => hits.sort_by(|a, b| a.result.cmp(&b.result)),
+            _ => hits.sort_by_key(|h| h.trace_idx),
+        }
+        if *sort_desc {
+            hits.reverse();
+        }
+
+        let view = self.coord_hits_view.as_mut().unwrap();
+        view.hits = hits;
+        view.computed = Some(key);
+        true
+    }
+
+    // Every call to fn_ns/fn_name in this thread, in call order
+    pub fn fn_calls(&self, fn_ns: &str, fn_name: &str) -> Vec<FnCall> {
+        let mut calls = Vec::new();
+        if let Some(cst) = &self.call_stack_tree {
+            let traces = &self.execution.traces;
+            cst.walk(&mut |node, depth| {
+                if let ExecTrace::FnCallTrace(fct) = &traces[node.trace_idx] {
+                    if fct.fn_ns == fn_ns && fct.fn_name == fn_name {
+                        let ret_trace = node.ret_trace_idx.map(|idx| &traces[idx]);
+                        let (result, duration) = match ret_trace {
+                            Some(ExecTrace::ExprTrace(et)) => (
+                                Some(et.result.clone()),
+                                Some(et.timestamp.saturating_sub(fct.timestamp)),
+                            ),
+                            _ => (None, None),
+                        };
+                        calls.push(FnCall {
+                            trace_idx: node.trace_idx,
+                            args_vec: fct.args_vec.clone(),
+                            result,
+                            depth,
+                            duration,
+                        });
+                    }
                 }
-            }
+            });
         }
-        let mut bindings_vec: Vec<(&str, &str)> = bindings.into_iter().collect();
-        bindings_vec.sort_by_key(|t| t.1);
-        bindings_vec
+        calls.sort_by_key(|c| c.trace_idx);
+        calls
+    }
+
+    pub fn show_fn_calls(&mut self, fn_ns: &str, fn_name: &str) {
+        self.fn_calls_view = Some(FnCallsView {
+            fn_ns: fn_ns.to_string(),
+            fn_name: fn_name.to_string(),
+            table: TableState::default(),
+            calls: Vec::new(),
+            computed: None,
+        });
+        self.selected_flow_tool = FlowTool::FnCalls;
     }
 
-    pub fn update_value_inspector(&mut self, value: &str) {
-        if let Some(result_pf) = lisp_reader::read_str(value) {
-            self.value_inspector = Some(result_pf);
+    // Computes the view rows again when new traces arrived or its filter or sort
+    // changed. Returns whether it did.
+    pub fn update_fn_calls(&mut self) -> bool {
+        let trace_count = self.execution.traces.len();
+        let (fn_ns, fn_name, key) = match &self.fn_calls_view {
+            Some(view) => (
+                view.fn_ns.clone(),
+                view.fn_name.clone(),
+                table_key(trace_count, &view.table),
+            ),
+            None => return false,
+        };
+        if self.fn_calls_view.as_ref().unwrap().computed.as_ref() == Some(&key) {
+            return false;
+        }
+
+        let (_, filter, sort_col, sort_desc) = &key;
+        let mut calls = self.fn_calls(&fn_ns, &fn_name);
+        if !filter.is_empty() {
+            calls.retain(|c| {
+                c.args_vec.contains(filter) || c.result.as_ref().is_some_and(|r| r.contains(filter))
+            });
         }
+        match sort_col {
+            1 => calls.sort_by(|a, b| a.args_vec.cmp(&b.args_vec)),
+            2 => calls.sort_by(|a, b| a.result.cmp(&b.result)),
+            3 => calls.sort_by_key(|c| c.depth),
+            4 => calls.sort_by_key(|c| c.duration),
+            _ => calls.sort_by_key(|c| c.trace_idx),
+        }
+        if *sort_desc {
+            calls.reverse();
+        }
+
+        let view = self.fn_calls_view.as_mut().unwrap();
+        view.calls = calls;
+        view.computed = Some(key);
+        true
+    }
+
+    pub fn current_locals(&self) -> Vec<(&str, &str)> {
+        locals_at(&self.execution, &self.bind_traces)
     }
 
     fn update_value_inspector_with_current_trace(&mut self) {
-        if let ExecTrace::ExprTrace(et) = self.execution.executing_trace().clone() {
-            self.update_value_inspector(&et.result);
+        let trace_idx = self.execution.curr_trace_idx;
+        let value = match self.execution.executing_trace() {
+            ExecTrace::ExprTrace(et) => &et.result,
+            ExecTrace::FnCallTrace(_) => {
+                self.value_inspector = ValueInspector::Empty;
+                return;
+            }
+        };
+
+        if let Some(parsed) = self.value_cache.get(&trace_idx) {
+            self.value_inspector = ValueInspector::Ready(Arc::clone(parsed));
+        } else {
+            self.value_parser
+                .get_or_insert_with(ValueParser::start)
+                .parse(trace_idx, value.clone());
+            self.value_inspector = ValueInspector::Parsing(trace_idx);
+        }
+    }
+
+    // The inspected value laid out in width chars, None until it is read or when it
+    // couldn't be
+    pub fn result_print_tokens(&mut self, width: usize) -> Option<Arc<Vec<PrintToken>>> {
+        let width = layout_width(width);
+        let parsed = match &self.value_inspector {
+            ValueInspector::Ready(parsed) => Arc::clone(parsed),
+            _ => return None,
+        };
+        if let Some(layout) = &self.result_layout {
+            if Arc::ptr_eq(&layout.value, &parsed) && layout.width == width {
+                return Some(Arc::clone(&layout.tokens));
+            }
+        }
+        let tokens = Arc::new(style_lisp_value(parsed.as_ref().as_ref().ok()?, width));
+        self.result_layout = Some(ValueLayout {
+            value: parsed,
+            width,
+            tokens: Arc::clone(&tokens),
+        });
+        Some(tokens)
+    }
+
+    // The current locals with their values laid out in width chars. Values are None
+    // while the worker reads them, and when they couldn't be read.
+    pub fn current_locals_print_tokens(&mut self, width: usize) -> Vec<(&str, &str, LocalTokens)> {
+        let width = layout_width(width);
+        let trace_idx = self.execution.curr_trace_idx;
+        // stepping away stops waiting, coming back asks again for what is missing
+        if self.locals_parsing.is_some_and(|idx| idx != trace_idx) {
+            self.locals_parsing = None;
+        }
+        let mut locals = Vec::new();
+        let mut misses = Vec::new();
+        for (symb, val) in locals_at(&self.execution, &self.bind_traces) {
+            let key = (trace_idx, symb.to_string(), width);
+            if let Some(tokens) = self.locals_layouts.get(&key) {
+                locals.push((symb, val, tokens.clone()));
+                continue;
+            }
+            let tokens = match self.locals_cache.get(&(trace_idx, key.1.clone())) {
+                Some(parsed) => parsed
+                    .as_ref()
+                    .as_ref()
+                    .ok()
+                    .map(|form| Arc::new(style_lisp_value(form, width))),
+                None => {
+                    misses.push(((trace_idx, key.1), val.to_string()));
+                    locals.push((symb, val, None));
+                    continue;
+                }
+            };
+            self.locals_layouts.insert(key, tokens.clone());
+            locals.push((symb, val, tokens));
+        }
+
+        if !misses.is_empty()
+            && self.locals_parsing != Some(trace_idx)
+            && self.locals_failed != Some(trace_idx)
+        {
+            self.locals_parser
+                .get_or_insert_with(ValueParser::start)
+                .parse_all(misses);
+            self.locals_parsing = Some(trace_idx);
+        }
+        locals
+    }
+
+    // Collects the values the workers parsed so far, returns true while the inspector
+    // or the locals are still waiting for some
+    pub fn poll_value_parser(&mut self) -> bool {
+        match self.value_parser.as_ref().map(|p| p.parsed_values()) {
+            Some(Ok(values)) => {
+                for (trace_idx, parsed) in values {
+                    if matches!(self.value_inspector, ValueInspector::Parsing(idx) if idx == trace_idx)
+                    {
+                        self.value_inspector = ValueInspector::Ready(Arc::clone(&parsed));
+                    }
+                    self.value_cache.insert(trace_idx, parsed);
+                }
+            }
+            // the next value we inspect starts a new worker
+            Some(Err(e)) => {
+                if let ValueInspector::Parsing(_) = self.value_inspector {
+                    self.value_inspector = ValueInspector::Failed(e);
+                }
+                self.value_parser = None;
+            }
+            None => (),
+        }
+        match self.locals_parser.as_ref().map(|p| p.parsed_values()) {
+            Some(Ok(values)) => {
+                for (key, parsed) in values {
+                    if self.locals_parsing == Some(key.0) {
+                        self.locals_parsing = None;
+                    }
+                    self.locals_cache.insert(key, parsed);
+                }
+            }
+            Some(Err(_)) => {
+                self.locals_failed = self.locals_parsing.take();
+                self.locals_parser = None;
+            }
+            None => (),
         }
+        matches!(self.value_inspector, ValueInspector::Parsing(_)) || self.locals_parsing.is_some()
     }
 
     pub fn step_next(&mut self) {
         self.execution.step_next();
+        self.nav_history.step(self.execution.curr_trace_idx);
         self.update_value_inspector_with_current_trace();
     }
 
     pub fn step_back(&mut self) {
         self.execution.step_back();
+        self.nav_history.step(self.execution.curr_trace_idx);
         self.update_value_inspector_with_current_trace();
     }
 
     pub fn jump_to(&mut self, trace_idx: &usize) {
-        self.execution.jump_to(trace_idx);
+        if !self.execution.jump_to(trace_idx) {
+            return;
+        }
+        self.nav_history.visit(*trace_idx);
         self.update_value_inspector_with_current_trace();
     }
+
+    // Lays out the flamegraph again if new traces arrived or the weight changed
+    pub fn update_flamegraph(&mut self) {
+        let key = (self.flamegraph_view.weight, self.execution.traces.len());
+        if self.flamegraph_view.laid_out != Some(key) {
+            self.flamegraph_view.rects = flamegraph::icicle_rects(self, key.0);
+            self.flamegraph_view.laid_out = Some(key);
+        }
+    }
+
+    pub fn nav_back(&mut self) {
+        if let Some(trace_idx) = self.nav_history.back() {
+            self.execution.jump_to(&trace_idx);
+            self.update_value_inspector_with_current_trace();
+        }
+    }
+
+    pub fn nav_forward(&mut self) {
+        if let Some(trace_idx) = self.nav_history.forward() {
+            self.execution.jump_to(&trace_idx);
+            self.update_value_inspector_with_current_trace();
+        }
+    }
 }
 
 impl Flow {
     pub fn thread_ids(&self) -> Vec<ThreadId> {
         self.threads.keys().cloned().collect::<Vec<ThreadId>>()
     }
+
+    pub fn trace_count(&self) -> usize {
+        self.threads
+            .values()
+            .map(|t| t.execution.traces.len())
+            .sum()
+    }
+
+    // Recomputes the coverage if forms or traces were added since the last time
+    pub fn update_coverage(&mut self) {
+        if self.covered != Some(self.generation) {
+            self.coverage = coverage::flow_coverage(self);
+            self.covered = Some(self.generation);
+        }
+    }
+
+    // Recomputes the thread profiler view profile if new traces arrived since
+    // the last time, or if it switched between thread and whole flow scopes
+    pub fn update_thread_profile(&mut self, thread_id: ThreadId) {
+        if let Some(thread) = self.threads.get(&thread_id) {
+            let whole_flow = thread.profiler_view.whole_flow;
+            let trace_count = if whole_flow {
+                self.trace_count()
+            } else {
+                thread.execution.traces.len()
+            };
+
+            if thread.profiler_view.profiled != Some((whole_flow, trace_count)) {
+                let profile = if whole_flow {
+                    profiler::flow_profile(self)
+                } else {
+                    profiler::thread_profile(thread)
+                };
+                let view = &mut self.threads.get_mut(&thread_id).unwrap().profiler_view;
+                view.profile = profile;
+                view.profiled = Some((whole_flow, trace_count));
+            }
+        }
+    }
 }
 
 impl DebuggerState {
@@ -376,6 +1173,10 @@ impl DebuggerState {
                 threads: HashMap::new(),
                 selected_thread_id: None,
                 timestamp,
+                export_status: None,
+                coverage: Vec::new(),
+                generation: 0,
+                covered: None,
             };
             flow.forms.insert(form_id, form);
             e.insert(flow);
@@ -386,6 +1187,7 @@ impl DebuggerState {
             // Add form to the flow
             if let Some(flow) = self.flows.get_mut(&flow_id) {
                 flow.forms.insert(form_id, form);
+                flow.generation += 1;
             };
         }
     }
@@ -393,6 +1195,7 @@ impl DebuggerState {
     pub fn add_exec_trace(&mut self, flow_id: FlowId, thread_id: ThreadId, expr_trace: ExprTrace) {
         // Add the exec trace to the corresponding FlowThread initializing if necesary
         if let Some(flow) = self.flows.get_mut(&flow_id) {
+            flow.generation += 1;
             if let hash_map::Entry::Vacant(e) = flow.threads.entry(thread_id) {
                 // first exec_trace of the FlowThread, create a FlowThread, then add the trace
                 let mut thread = FlowThread::new(thread_id);
@@ -420,6 +1223,7 @@ impl DebuggerState {
         fn_call_trace: FnCallTrace,
     ) {
         if let Some(flow) = self.flows.get_mut(&flow_id) {
+            flow.generation += 1;
             if let hash_map::Entry::Vacant(e) = flow.threads.entry(thread_id) {
                 // first fn_call_trace of the FlowThread, create a FlowThread, then add the trace
                 let mut thread = FlowThread::new(thread_id);
@@ -442,6 +1246,7 @@ impl DebuggerState {
 
     pub fn add_bind_trace(&mut self, flow_id: FlowId, thread_id: ThreadId, bind_trace: BindTrace) {
         if let Some(flow) = self.flows.get_mut(&flow_id) {
+            flow.generation += 1;
             if let hash_map::Entry::Vacant(e) = flow.threads.entry(thread_id) {
                 // first fn_call_trace of the FlowThread, create a FlowThread, then add the trace
                 let mut thread = FlowThread::new(thread_id);
@@ -490,6 +1295,7 @@ impl DebuggerState {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lisp_pprinter::print_tokens_to_str;
 
     #[test]
     fn is_coord_in_scope_test() {
@@ -498,4 +1304,361 @@ mod tests {
         assert!(is_coord_in_scope(&vec![1, 2], &vec![1, 2, 3]));
         assert!(!is_coord_in_scope(&vec![1, 2, 3], &vec![1, 2]));
     }
+
+    #[test]
+    fn fn_calls_test() {
+        let mut thread = FlowThread::new(1);
+        let fn_call = |args: &str, timestamp| {
+            FnCallTrace::new(
+                1,
+                "user".to_string(),
+                "factorial".to_string(),
+                args.to_string(),
+                timestamp,
+            )
+        };
+        let ret = |result: &str, timestamp| {
+            ExprTrace::new(1, result.to_string(), vec![], true, timestamp)
+        };
+
+        thread.add_fn_call_trace(fn_call("[2]", 10));
+        thread.add_fn_call_trace(fn_call("[1]", 11));
+        thread.add_fn_call_trace(fn_call("[0]", 12));
+        thread.add_expr_trace(ret("1", 13));
+        thread.add_expr_trace(ret("1", 15));
+        thread.add_expr_trace(ret("2", 20));
+
+        let calls = thread.fn_calls("user", "factorial");
+        assert_eq!(
+            calls
+                .iter()
+                .map(|c| (c.trace_idx, c.depth, c.result.as_deref(), c.duration))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, 1, Some("2"), Some(10)),
+                (1, 2, Some("1"), Some(4)),
+                (2, 3, Some("1"), Some(1))
+            ]
+        );
+        assert!(thread.fn_calls("user", "other").is_empty());
+
+        thread.show_fn_calls("user", "factorial");
+        let view = thread.fn_calls_view.as_mut().unwrap();
+        view.table.filter = "[1]".to_string();
+        assert!(thread.update_fn_calls());
+        assert!(!thread.update_fn_calls());
+        assert_eq!(thread.fn_calls_view.as_ref().unwrap().calls.len(), 1);
+    }
+
+    #[test]
+    fn coord_hits_view_test() {
+        let mut thread = FlowThread::new(1);
+        let expr = |result: &str| ExprTrace::new(1, result.to_string(), vec![3], false, 0);
+        thread.add_expr_trace(expr("b"));
+        thread.add_expr_trace(expr("a"));
+
+        thread.show_coord_hits(1, &[3]);
+        assert!(thread.update_coord_hits());
+        assert!(!thread.update_coord_hits());
+
+        let view = thread.coord_hits_view.as_mut().unwrap();
+        view.table.sort_by(3);
+        assert!(thread.update_coord_hits());
+        let results = |thread: &FlowThread| {
+            thread
+                .coord_hits_view
+                .as_ref()
+                .unwrap()
+                .hits
+                .iter()
+                .map(|h| h.result.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(results(&thread), vec!["a", "b"]);
+
+        thread.add_expr_trace(expr("c"));
+        thread.coord_hits_view.as_mut().unwrap().table.filter = "c".to_string();
+        assert!(thread.update_coord_hits());
+        assert_eq!(results(&thread), vec!["c"]);
+    }
+
+    #[test]
+    fn flow_coverage_cache_test() {
+        let mut state = DebuggerState::new();
+        let form = |id: FormId, ns: &str| {
+            Form::new(id, ns.to_string(), "(defn f [a] (inc a))".to_string(), 0)
+        };
+        state.add_flow_form(0, 1, form(1, "user"), 0);
+        let executed = |state: &mut DebuggerState| {
+            let flow = state.flows.get_mut(&0).unwrap();
+            flow.update_coverage();
+            flow.coverage[0].forms[0].executed
+        };
+        assert_eq!(executed(&mut state), 0);
+
+        state.add_exec_trace(0, 1, ExprTrace::new(1, "2".to_string(), vec![3], false, 0));
+        assert_eq!(executed(&mut state), 1);
+
+        // re-evaluating a form doesn't change the form count
+        let total = |state: &mut DebuggerState| {
+            let flow = state.flows.get_mut(&0).unwrap();
+            flow.update_coverage();
+            flow.coverage[0].forms[0].total
+        };
+        let before = total(&mut state);
+        let bigger = "(defn f [a] (if (pos? a) (inc a) (dec a)))".to_string();
+        state.add_flow_form(0, 1, Form::new(1, "user".to_string(), bigger, 1), 0);
+        assert!(total(&mut state) > before);
+
+        state.add_flow_form(0, 2, form(2, "other"), 0);
+        let flow = state.flows.get_mut(&0).unwrap();
+        flow.update_coverage();
+        assert_eq!(flow.coverage.len(), 2);
+    }
+
+    #[test]
+    fn form_layouts_test() {
+        let form = Form::new(
+            1,
+            "user".to_string(),
+            "(defn f [a] (if (pos? a) (inc a) (dec a)))".to_string(),
+            0,
+        );
+        let lines = |tokens: &[PrintToken]| {
+            tokens
+                .iter()
+                .filter(|t| matches!(t, PrintToken::Newline))
+                .count()
+                + 1
+        };
+
+        let narrow = form.print_tokens(20);
+        let wide = form.print_tokens(200);
+        assert!(lines(&narrow) > lines(&wide));
+        // every width gets laid out only once, and close widths share the layout
+        assert!(Arc::ptr_eq(&narrow, &form.print_tokens(20)));
+        assert!(Arc::ptr_eq(&narrow, &form.print_tokens(23)));
+        assert!(!Arc::ptr_eq(&narrow, &form.print_tokens(24)));
+        assert_eq!(layout_width(3), LAYOUT_WIDTH_STEP);
+    }
+
+    #[test]
+    fn form_source_test() {
+        let form = Form::new(1, "user".to_string(), "(inc a)".to_string(), 0);
+        let source = Form::new(1, "user".to_string(), "(inc a)".to_string(), 0)
+            .with_source("(inc  a)".to_string());
+
+        assert!(Arc::ptr_eq(
+            &form.code_tokens(CodeLayout::Source, 40),
+            &form.print_tokens(40)
+        ));
+        assert_eq!(
+            print_tokens_to_str(&source.code_tokens(CodeLayout::Source, 40)),
+            "(inc  a)"
+        );
+        assert_eq!(
+            print_tokens_to_str(&source.code_tokens(CodeLayout::Pretty, 40)),
+            "(inc a)"
+        );
+
+        // unreadable sources are pretty printed
+        let broken = Form::new(1, "user".to_string(), "(inc a)".to_string(), 0)
+            .with_source("(inc a".to_string());
+        assert_eq!(
+            print_tokens_to_str(&broken.code_tokens(CodeLayout::Source, 40)),
+            "(inc a)"
+        );
+    }
+
+    #[test]
+    fn form_code_lines_test() {
+        let location = SourceLocation {
+            file: Some("src/user.clj".to_string()),
+            line: 10,
+            column: Some(1),
+        };
+        let form = Form::new(1, "user".to_string(), "(defn f [a] (inc a))".to_string(), 0)
+            .with_source("(defn f [a]\n\n  (inc a))".to_string());
+        let code_lines =
+            |form: &Form, layout| form.code_lines(layout, &form.code_tokens(layout, 40));
+
+        assert!(code_lines(&form, CodeLayout::Source).is_empty());
+
+        let form = form.with_location(location.clone());
+        assert_eq!(
+            code_lines(&form, CodeLayout::Source),
+            vec![Some(10), Some(11), Some(12)]
+        );
+        assert_eq!(
+            code_lines(&form, CodeLayout::Pretty),
+            vec![Some(10), Some(12)]
+        );
+
+        // without a source we only know where the form starts
+        let form = Form::new(1, "user".to_string(), "(defn f [a] (inc a))".to_string(), 0)
+            .with_location(location.clone());
+        assert_eq!(code_lines(&form, CodeLayout::Source), vec![Some(10), None]);
+
+        assert_eq!(location.to_string(), "src/user.clj:10");
+        let location = SourceLocation {
+            file: None,
+            ..location
+        };
+        assert_eq!(location.to_string(), "line 10");
+    }
+
+    #[test]
+    fn unreadable_form_test() {
+        let form = Form::new(1, "user".to_string(), "(defn f [a] (inc a)".to_string(), 0);
+        assert!(form.lisp_form.is_err());
+        assert!(form.print_tokens(40).is_empty());
+    }
+
+    #[test]
+    fn coord_heat_test() {
+        let mut thread = FlowThread::new(1);
+        let expr = |coord| ExprTrace::new(1, "nil".to_string(), coord, false, 0);
+
+        for _ in 0..7 {
+            thread.add_expr_trace(expr(vec![3, 1]));
+        }
+        thread.add_expr_trace(expr(vec![3]));
+
+        assert_eq!(thread.coord_hit_count(1, &vec![3, 1]), 7);
+        assert_eq!(thread.coord_hit_count(1, &vec![2]), 0);
+        assert_eq!(thread.heat(7), 1.0);
+        assert!((thread.heat(1) - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(thread.heat(0), 0.0);
+    }
+
+    #[test]
+    fn hot_coords_mode_test() {
+        let mut thread = FlowThread::new(1);
+        let expr = |coord| ExprTrace::new(1, "nil".to_string(), coord, false, 0);
+
+        thread.add_expr_trace(expr(vec![3, 1]));
+        thread.add_expr_trace(expr(vec![3, 2]));
+        thread.add_expr_trace(expr(vec![3, 1]));
+
+        thread.execution.curr_trace_idx = 1;
+        assert!(thread.is_coord_hot(1, &vec![3, 2]));
+        assert!(!thread.is_coord_pending(1, &vec![3, 2]));
+
+        thread.execution.curr_trace_idx = 0;
+        assert!(thread.is_coord_hot(1, &vec![3, 2]));
+        thread.hot_coords_mode = HotCoordsMode::UpToCurrent;
+        assert!(thread.is_coord_hot(1, &vec![3, 1]));
+        assert!(!thread.is_coord_hot(1, &vec![3, 2]));
+        assert!(!thread.is_coord_pending(1, &vec![3, 2]));
+        thread.hot_coords_mode = HotCoordsMode::UpToCurrentAndAfter;
+        assert!(!thread.is_coord_hot(1, &vec![3, 2]));
+        assert!(thread.is_coord_pending(1, &vec![3, 2]));
+
+        // hits only count up to the current step too
+        assert_eq!(thread.coord_hit_count(1, &vec![3, 1]), 1);
+        thread.execution.curr_trace_idx = 2;
+        assert_eq!(thread.coord_hit_count(1, &vec![3, 1]), 2);
+        thread.hot_coords_mode = HotCoordsMode::Cumulative;
+        thread.execution.curr_trace_idx = 0;
+        assert_eq!(thread.coord_hit_count(1, &vec![3, 1]), 2);
+        assert!(!thread.is_coord_pending(1, &vec![4]));
+    }
+
+    #[test]
+    fn value_inspector_test() {
+        let mut thread = FlowThread::new(1);
+        let expr = |result: &str| ExprTrace::new(1, result.to_string(), vec![], false, 0);
+        thread.add_expr_trace(expr("[1 2]"));
+        thread.add_expr_trace(expr("{:a"));
+
+        let wait_for_value = |thread: &mut FlowThread| {
+            for _ in 0..500 {
+                if !thread.poll_value_parser() {
+                    return;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            panic!("value never got parsed");
+        };
+
+        thread.jump_to(&0);
+        assert!(matches!(thread.value_inspector, ValueInspector::Parsing(0)));
+        wait_for_value(&mut thread);
+        match &thread.value_inspector {
+            ValueInspector::Ready(parsed) => {
+                assert_eq!(parsed.as_ref().as_ref().unwrap().to_string(), "[1 2]")
+            }
+            v => panic!("{:?}", v),
+        }
+
+        thread.step_next();
+        wait_for_value(&mut thread);
+        assert!(matches!(&thread.value_inspector, ValueInspector::Ready(p) if p.is_err()));
+
+        // values we already parsed are ready right away
+        thread.step_back();
+        assert!(matches!(&thread.value_inspector, ValueInspector::Ready(p) if p.is_ok()));
+    }
+
+    #[test]
+    fn value_print_tokens_test() {
+        let mut thread = FlowThread::new(1);
+        assert!(thread.result_print_tokens(40).is_none());
+
+        thread.value_inspector = ValueInspector::Ready(Arc::new(read_str("{:a 1 :bbb 2}")));
+        let tokens = thread.result_print_tokens(40).unwrap();
+        assert_eq!(print_tokens_to_str(&tokens), "{:a 1 :bbb 2}");
+        assert!(Arc::ptr_eq(
+            &tokens,
+            &thread.result_print_tokens(40).unwrap()
+        ));
+        assert_eq!(
+            print_tokens_to_str(&thread.result_print_tokens(12).unwrap()),
+            "{:a   1\n :bbb 2}"
+        );
+
+        thread.value_inspector = ValueInspector::Ready(Arc::new(read_str("{:a")));
+        assert!(thread.result_print_tokens(40).is_none());
+    }
+
+    #[test]
+    fn locals_print_tokens_test() {
+        let mut thread = FlowThread::new(1);
+        let bind = |symbol: &str, value: &str| {
+            BindTrace::new(1, symbol.to_string(), value.to_string(), vec![], 0)
+        };
+        thread.add_bind_trace(bind("a", "[{:a 1} {:b 2}]"));
+        thread.add_bind_trace(bind("b", "(inc"));
+        thread.add_expr_trace(ExprTrace::new(1, "1".to_string(), vec![3], false, 1));
+
+        // nothing is read on the UI thread
+        let pretty = |thread: &mut FlowThread| {
+            thread
+                .current_locals_print_tokens(10)
+                .into_iter()
+                .map(|(symb, _, tokens)| {
+                    (symb.to_string(), tokens.map(|t| print_tokens_to_str(&t)))
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            pretty(&mut thread),
+            vec![("b".to_string(), None), ("a".to_string(), None)]
+        );
+
+        for _ in 0..500 {
+            if !thread.poll_value_parser() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(!thread.poll_value_parser());
+        assert_eq!(
+            pretty(&mut thread),
+            vec![
+                ("b".to_string(), None),
+                ("a".to_string(), Some("[{:a 1}\n {:b 2}]".to_string()))
+            ]
+        );
+    }
 }
@@ -0,0 +1,280 @@
+use crate::indent_rules::indent_rules;
+use crate::lisp_pprinter::PrintToken;
+use crate::lisp_reader::AtomKind;
+use egui::{Color32, Visuals};
+
+// Syntax highlighting for print tokens. Hot and current coords are colored on top
+// of it, so it only colors what isn't being traced.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyntaxClass {
+    // special forms, core macros and symbols with user indentation rules
+    SpecialForm,
+    Keyword,
+    String,
+    Number,
+    Regexp,
+    // tagged literals like #inst
+    Tagged,
+    // nil, booleans and chars
+    Constant,
+    Comment,
+    Plain,
+}
+
+const SPECIAL_SYMBS: &[&str] = &[
+    "def",
+    "if",
+    "do",
+    "let*",
+    "letfn*",
+    "quote",
+    "var",
+    "fn*",
+    "loop*",
+    "recur",
+    "throw",
+    "try",
+    "catch",
+    "finally",
+    "monitor-enter",
+    "monitor-exit",
+    "new",
+    "set!",
+    ".",
+    "case*",
+    "reify*",
+    "deftype*",
+    "let",
+    "letfn",
+    "fn",
+    "loop",
+    "defn",
+    "defn-",
+    "defmacro",
+    "defmethod",
+    "defmulti",
+    "defonce",
+    "defrecord",
+    "deftype",
+    "defprotocol",
+    "extend-protocol",
+    "extend-type",
+    "declare",
+    "ns",
+    "when",
+    "when-not",
+    "when-let",
+    "when-some",
+    "when-first",
+    "if-not",
+    "if-let",
+    "if-some",
+    "cond",
+    "condp",
+    "case",
+    "cond->",
+    "cond->>",
+    "->",
+    "->>",
+    "as->",
+    "some->",
+    "some->>",
+    "doto",
+    "and",
+    "or",
+    "for",
+    "doseq",
+    "dotimes",
+    "while",
+    "binding",
+    "with-open",
+    "with-redefs",
+    "with-local-vars",
+    "comment",
+    "reify",
+    "proxy",
+];
+
+fn symbol_class(ns: Option<&str>, name: &str, text: &str) -> SyntaxClass {
+    let core = matches!(ns, None | Some("clojure.core") | Some("cljs.core"));
+    if (core && SPECIAL_SYMBS.contains(&name)) || indent_rules().rule_for(text).is_some() {
+        SyntaxClass::SpecialForm
+    } else {
+        SyntaxClass::Plain
+    }
+}
+
+pub fn token_class(token: &PrintToken) -> SyntaxClass {
+    match token {
+        PrintToken::String(_) => SyntaxClass::String,
+        PrintToken::Regexp(_) => SyntaxClass::Regexp,
+        PrintToken::Comment(_) => SyntaxClass::Comment,
+        PrintToken::Atomic {
+            kind: Some(kind),
+            val,
+            ..
+        } => match kind {
+            AtomKind::Keyword { .. } => SyntaxClass::Keyword,
+            AtomKind::Number(_) => SyntaxClass::Number,
+            AtomKind::Char | AtomKind::Nil | AtomKind::Bool(_) => SyntaxClass::Constant,
+            AtomKind::Symbol { ns, name } => symbol_class(ns.as_deref(), name, val),
+        },
+        // of the syntax around forms only tags are highlighted, #? #' and ^ aren't tags
+        PrintToken::Atomic {
+            val, kind: None, ..
+        } if val.starts_with('#') && val[1..].starts_with(char::is_alphabetic) => {
+            SyntaxClass::Tagged
+        }
+        PrintToken::Atomic { .. }
+        | PrintToken::BlockOpen { .. }
+        | PrintToken::BlockClose { .. }
+        | PrintToken::Text(_)
+        | PrintToken::Space
+        | PrintToken::Newline => SyntaxClass::Plain,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SyntaxTheme {
+    special_form: Color32,
+    keyword: Color32,
+    string: Color32,
+    number: Color32,
+    regexp: Color32,
+    tagged: Color32,
+    constant: Color32,
+    comment: Color32,
+}
+
+impl SyntaxTheme {
+    pub fn dark() -> Self {
+        SyntaxTheme {
+            special_form: Color32::from_rgb(198, 120, 221),
+            keyword: Color32::from_rgb(97, 175, 239),
+            string: Color32::from_rgb(152, 195, 121),
+            number: Color32::from_rgb(209, 154, 102),
+            regexp: Color32::from_rgb(86, 182, 194),
+            tagged: Color32::from_rgb(224, 108, 117),
+            constant: Color32::from_rgb(209, 154, 102),
+            comment: Color32::from_rgb(110, 118, 129),
+        }
+    }
+
+    pub fn light() -> Self {
+        SyntaxTheme {
+            special_form: Color32::from_rgb(166, 38, 164),
+            keyword: Color32::from_rgb(64, 120, 242),
+            string: Color32::from_rgb(80, 161, 79),
+            number: Color32::from_rgb(152, 104, 1),
+            regexp: Color32::from_rgb(1, 132, 188),
+            tagged: Color32::from_rgb(228, 86, 73),
+            constant: Color32::from_rgb(152, 104, 1),
+            comment: Color32::from_rgb(140, 140, 140),
+        }
+    }
+
+    // Follows the dark/light mode switch
+    pub fn for_visuals(visuals: &Visuals) -> Self {
+        if visuals.dark_mode {
+            Self::dark()
+        } else {
+            Self::light()
+        }
+    }
+
+    // None for plain tokens, that keep the default text color
+    pub fn color(&self, class: SyntaxClass) -> Option<Color32> {
+        match class {
+            SyntaxClass::SpecialForm => Some(self.special_form),
+            SyntaxClass::Keyword => Some(self.keyword),
+            SyntaxClass::String => Some(self.string),
+            SyntaxClass::Number => Some(self.number),
+            SyntaxClass::Regexp => Some(self.regexp),
+            SyntaxClass::Tagged => Some(self.tagged),
+            SyntaxClass::Constant => Some(self.constant),
+            SyntaxClass::Comment => Some(self.comment),
+            SyntaxClass::Plain => None,
+        }
+    }
+
+    pub fn token_color(&self, token: &PrintToken) -> Option<Color32> {
+        self.color(token_class(token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lisp_pprinter::{lisp_form_print_tokens, source_print_tokens};
+    use crate::lisp_reader::{read_str, read_str_with_spans};
+
+    #[test]
+    fn token_class_test() {
+        let form =
+            read_str("(defn f [a] (when a (str :k \"s\" #\"r\" 1.5 nil \\c #inst \"2020\" #'g)))")
+                .unwrap();
+        let classes = lisp_form_print_tokens(&form)
+            .iter()
+            .filter_map(|t| match t {
+                PrintToken::Atomic { val, .. } => Some((val.clone(), token_class(t))),
+                PrintToken::String(s) => Some((s.clone(), token_class(t))),
+                PrintToken::Regexp(s) => Some((s.clone(), token_class(t))),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let expected = [
+            ("defn", SyntaxClass::SpecialForm),
+            ("f", SyntaxClass::Plain),
+            ("a", SyntaxClass::Plain),
+            ("when", SyntaxClass::SpecialForm),
+            ("a", SyntaxClass::Plain),
+            ("str", SyntaxClass::Plain),
+            (":k", SyntaxClass::Keyword),
+            ("s", SyntaxClass::String),
+            ("r", SyntaxClass::Regexp),
+            ("1.5", SyntaxClass::Number),
+            ("nil", SyntaxClass::Constant),
+            ("\\c", SyntaxClass::Constant),
+            ("#inst", SyntaxClass::Tagged),
+            ("2020", SyntaxClass::String),
+            ("#'", SyntaxClass::Plain),
+            ("g", SyntaxClass::Plain),
+        ];
+        assert_eq!(
+            classes,
+            expected
+                .iter()
+                .map(|(v, c)| (v.to_string(), *c))
+                .collect::<Vec<_>>()
+        );
+
+        // source tokens are classified too
+        let source = "(when x :k) ; c";
+        let (_, spans) = read_str_with_spans(source).unwrap();
+        let classes = source_print_tokens(source, &spans)
+            .iter()
+            .map(token_class)
+            .filter(|c| *c != SyntaxClass::Plain)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            classes,
+            vec![
+                SyntaxClass::SpecialForm,
+                SyntaxClass::Keyword,
+                SyntaxClass::Comment
+            ]
+        );
+
+        assert_eq!(
+            token_class(&PrintToken::Comment("; hi".to_string())),
+            SyntaxClass::Comment
+        );
+        assert_ne!(
+            SyntaxTheme::light().color(SyntaxClass::Keyword),
+            SyntaxTheme::dark().color(SyntaxClass::Keyword)
+        );
+        assert_eq!(SyntaxTheme::dark().color(SyntaxClass::Plain), None);
+    }
+}
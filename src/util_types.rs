@@ -1,5 +1,6 @@
 use crate::state::{ExecTrace, FnCallTrace, Form, FormId};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
 use std::sync::{Arc, Mutex};
 
 #[derive(Debug)]
@@ -33,22 +34,23 @@ impl SortedForms {
         self.forms_map.get_mut(form_id)
     }
 
+    // Re-evaluated forms replace the ones with the same id
     pub fn insert(&mut self, form_id: FormId, form: Form) {
-        if let std::collections::hash_map::Entry::Vacant(e) = self.forms_map.entry(form_id) {
-            let timestamp = form.timestamp;
-            e.insert(form);
-            let idx_result = self
-                .order
-                .as_slice()
-                .binary_search_by(|(ts, _)| ts.cmp(&timestamp).reverse());
+        let timestamp = form.timestamp;
+        if self.forms_map.insert(form_id, form).is_some() {
+            self.order.retain(|(_, id)| *id != form_id);
+        }
+        let idx_result = self
+            .order
+            .as_slice()
+            .binary_search_by(|(ts, _)| ts.cmp(&timestamp).reverse());
 
-            match idx_result {
-                Ok(idx) => {
-                    self.order.insert(idx, (timestamp, form_id));
-                }
-                Err(idx) => {
-                    self.order.insert(idx, (timestamp, form_id));
-                }
+        match idx_result {
+            Ok(idx) => {
+                self.order.insert(idx, (timestamp, form_id));
+            }
+            Err(idx) => {
+                self.order.insert(idx, (timestamp, form_id));
             }
         }
     }
@@ -79,6 +81,7 @@ impl<'a> Iterator for SortedFormsIter<'a> {
 #[derive(Debug)]
 pub struct CallStackTreeNode {
     pub trace_idx: usize,
+    pub ret_trace_idx: Option<usize>,
     pub childs: Vec<Arc<Mutex<CallStackTreeNode>>>,
 }
 
@@ -93,6 +96,7 @@ impl CallStackTree {
         let mut tree = Self {
             root: Arc::new(Mutex::new(CallStackTreeNode {
                 trace_idx,
+                ret_trace_idx: None,
                 childs: vec![],
             })),
             stack: vec![],
@@ -105,6 +109,7 @@ impl CallStackTree {
     pub fn call(&mut self, trace_idx: usize) {
         let node_pointer = Arc::new(Mutex::new(CallStackTreeNode {
             trace_idx,
+            ret_trace_idx: None,
             childs: vec![],
         }));
 
@@ -125,8 +130,210 @@ impl CallStackTree {
         self.stack.push(stack_node_pointer);
     }
 
-    pub fn pop(&mut self) {
-        self.stack.pop();
+    pub fn pop(&mut self, ret_trace_idx: usize) {
+        if let Some(node_pointer) = self.stack.pop() {
+            node_pointer.lock().unwrap().ret_trace_idx = Some(ret_trace_idx);
+        }
+    }
+
+    // Depth first walk over the tree, calling f with every node and its depth,
+    // starting at 1 for the root
+    pub fn walk(&self, f: &mut dyn FnMut(&CallStackTreeNode, usize)) {
+        fn walk_node(
+            node_pointer: &Arc<Mutex<CallStackTreeNode>>,
+            depth: usize,
+            f: &mut dyn FnMut(&CallStackTreeNode, usize),
+        ) {
+            let node = node_pointer.lock().unwrap();
+            f(&node, depth);
+            for child in &node.childs {
+                walk_node(child, depth + 1, f);
+            }
+        }
+        walk_node(&self.root, 1, f);
+    }
+
+    // Returns the trace_idx of the innermost fn call that was executing when
+    // trace_idx was recorded
+    pub fn enclosing_call(&self, trace_idx: usize) -> Option<usize> {
+        let contains = |node: &CallStackTreeNode| {
+            node.trace_idx <= trace_idx && node.ret_trace_idx.is_none_or(|r| trace_idx <= r)
+        };
+
+        let mut node_pointer = Arc::clone(&self.root);
+        if !contains(&node_pointer.lock().unwrap()) {
+            return None;
+        }
+
+        loop {
+            let next_pointer = {
+                let node = node_pointer.lock().unwrap();
+                // childs are sorted by trace_idx, so only the last one starting
+                // before trace_idx can contain it
+                let pos = node
+                    .childs
+                    .partition_point(|c| c.lock().unwrap().trace_idx <= trace_idx);
+                if pos == 0 {
+                    return Some(node.trace_idx);
+                }
+                let child_pointer = &node.childs[pos - 1];
+                if contains(&child_pointer.lock().unwrap()) {
+                    Arc::clone(child_pointer)
+                } else {
+                    return Some(node.trace_idx);
+                }
+            };
+            node_pointer = next_pointer;
+        }
+    }
+}
+
+// Sorting, filtering and paging state for the table views
+#[derive(Debug, Default)]
+pub struct TableState {
+    pub sort_col: usize,
+    pub sort_desc: bool,
+    pub filter: String,
+    pub page: usize,
+}
+
+impl TableState {
+    pub const PAGE_SIZE: usize = 50;
+
+    pub fn sort_by(&mut self, col: usize) {
+        if self.sort_col == col {
+            self.sort_desc = !self.sort_desc;
+        } else {
+            self.sort_col = col;
+            self.sort_desc = false;
+        }
+    }
+
+    pub fn page_count(total: usize) -> usize {
+        usize::max(1, total.div_ceil(Self::PAGE_SIZE))
+    }
+
+    pub fn page_range(&self, total: usize) -> std::ops::Range<usize> {
+        let page = usize::min(self.page, Self::page_count(total) - 1);
+        let start = page * Self::PAGE_SIZE;
+        start..usize::min(total, start + Self::PAGE_SIZE)
+    }
+}
+
+// Browser like history of visited trace indexes. Jumps push a new entry,
+// while consecutive steps keep updating the same one so single stepping
+// doesn't flood the history
+#[derive(Debug)]
+pub struct NavHistory {
+    entries: Vec<usize>,
+    pos: usize,
+    stepping: bool,
+}
+
+impl NavHistory {
+    pub fn new(trace_idx: usize) -> Self {
+        Self {
+            entries: vec![trace_idx],
+            pos: 0,
+            stepping: false,
+        }
+    }
+
+    fn push(&mut self, trace_idx: usize) {
+        // visiting something new discards the forward entries
+        self.entries.truncate(self.pos + 1);
+        if self.entries[self.pos] != trace_idx {
+            self.entries.push(trace_idx);
+            self.pos = self.entries.len() - 1;
+        }
+    }
+
+    pub fn visit(&mut self, trace_idx: usize) {
+        self.push(trace_idx);
+        self.stepping = false;
+    }
+
+    pub fn step(&mut self, trace_idx: usize) {
+        if self.stepping {
+            self.entries.truncate(self.pos + 1);
+            self.entries[self.pos] = trace_idx;
+        } else {
+            self.push(trace_idx);
+            self.stepping = true;
+        }
+    }
+
+    pub fn can_go_back(&self) -> bool {
+        self.pos > 0
+    }
+
+    pub fn can_go_forward(&self) -> bool {
+        self.pos + 1 < self.entries.len()
+    }
+
+    pub fn back(&mut self) -> Option<usize> {
+        if self.can_go_back() {
+            self.pos -= 1;
+            self.stepping = false;
+            Some(self.entries[self.pos])
+        } else {
+            None
+        }
+    }
+
+    pub fn forward(&mut self) -> Option<usize> {
+        if self.can_go_forward() {
+            self.pos += 1;
+            self.stepping = false;
+            Some(self.entries[self.pos])
+        } else {
+            None
+        }
+    }
+}
+
+// A map that keeps only the capacity most recently used entries. Lookups are
+// linear on the usage order, which is fine for the few entries we keep
+#[derive(Debug, Clone)]
+pub struct LruCache<K, V> {
+    capacity: usize,
+    entries: HashMap<K, V>,
+    // least recently used first
+    order: VecDeque<K>,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn touch(&mut self, key: &K) {
+        if let Some(pos) = self.order.iter().position(|k| k == key) {
+            let k = self.order.remove(pos).unwrap();
+            self.order.push_back(k);
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.touch(key);
+        self.entries.get(key)
+    }
+
+    pub fn insert(&mut self, key: K, val: V) {
+        if self.entries.insert(key.clone(), val).is_some() {
+            self.touch(&key);
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
     }
 }
 
@@ -167,4 +374,78 @@ mod tests {
         let rv = sfs.iter().map(|f| f.timestamp).collect::<Vec<u64>>();
         assert_eq!(rv, vec![70, 50, 20, 11, 10]);
     }
+
+    #[test]
+    fn nav_history_test() {
+        let mut h = NavHistory::new(0);
+        assert_eq!(h.back(), None);
+
+        h.visit(10);
+        // consecutive steps collapse into a single entry
+        h.step(11);
+        h.step(12);
+        h.step(13);
+        h.visit(50);
+
+        assert_eq!(h.back(), Some(13));
+        assert_eq!(h.back(), Some(10));
+        assert_eq!(h.back(), Some(0));
+        assert_eq!(h.back(), None);
+        assert_eq!(h.forward(), Some(10));
+
+        // visiting from the middle drops the forward entries
+        h.visit(20);
+        assert_eq!(h.forward(), None);
+        assert_eq!(h.back(), Some(10));
+        assert_eq!(h.forward(), Some(20));
+    }
+
+    #[test]
+    fn call_stack_tree_enclosing_call_test() {
+        // 0 (f
+        // 1   (g)    ret 2
+        // 3   (g
+        // 4     (h)  ret 5
+        let mut cst = CallStackTree::new(0);
+        cst.call(1);
+        cst.pop(2);
+        cst.call(3);
+        cst.call(4);
+        cst.pop(5);
+
+        assert_eq!(cst.enclosing_call(0), Some(0));
+        assert_eq!(cst.enclosing_call(2), Some(1));
+        assert_eq!(cst.enclosing_call(3), Some(3));
+        assert_eq!(cst.enclosing_call(6), Some(3));
+        assert_eq!(cst.enclosing_call(5), Some(4));
+    }
+
+    #[test]
+    fn table_state_page_range_test() {
+        let mut table = TableState::default();
+        assert_eq!(table.page_range(0), 0..0);
+        assert_eq!(table.page_range(120), 0..50);
+        table.page = 2;
+        assert_eq!(table.page_range(120), 100..120);
+        table.page = 5;
+        assert_eq!(table.page_range(120), 100..120);
+    }
+
+    #[test]
+    fn lru_cache_test() {
+        let mut cache = LruCache::new(2);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        // 1 is now the most recently used, so 2 goes first
+        assert_eq!(cache.get(&1), Some(&"a"));
+        cache.insert(3, "c");
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&3), Some(&"c"));
+
+        cache.insert(1, "A");
+        cache.insert(4, "d");
+        assert_eq!(cache.get(&1), Some(&"A"));
+        assert_eq!(cache.get(&3), None);
+        assert_eq!(cache.get(&4), Some(&"d"));
+    }
 }
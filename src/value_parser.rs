@@ -0,0 +1,114 @@
+use crate::lisp_reader::{read_str_with_limits, PrintableLispForm, ReadError, ReadLimits};
+use std::sync::mpsc::{self, Receiver, SendError, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+// Results can be huge, like a whole app state, so the inspector only reads this much of them
+pub const VALUE_INSPECTOR_READ_LIMITS: ReadLimits = ReadLimits {
+    max_depth: 32,
+    max_length: 1000,
+    max_string_length: 10_000,
+    elisions: true,
+};
+
+pub type ParsedValue = Arc<Result<PrintableLispForm, ReadError>>;
+
+// Reads inspected values on a worker thread, so stepping through big values doesn't
+// freeze the UI. Values are identified by a key, like the trace_idx of results.
+#[derive(Debug)]
+pub struct ValueParser<K> {
+    jobs: Sender<Vec<(K, String)>>,
+    parsed: Receiver<Vec<(K, ParsedValue)>>,
+}
+
+impl<K: Send + 'static> ValueParser<K> {
+    pub fn start() -> Self {
+        let (jobs, jobs_rx) = mpsc::channel::<Vec<(K, String)>>();
+        let (parsed_tx, parsed) = mpsc::channel();
+
+        // the worker ends when the parser, and with it the jobs sender, is dropped
+        thread::spawn(move || {
+            while let Ok(mut job) = jobs_rx.recv() {
+                // when stepping fast only the last requested values matter
+                while let Ok(newer) = jobs_rx.try_recv() {
+                    job = newer;
+                }
+                let values = job
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let form = read_str_with_limits(&value, VALUE_INSPECTOR_READ_LIMITS);
+                        (key, Arc::new(form))
+                    })
+                    .collect::<Vec<_>>();
+                if parsed_tx.send(values).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self { jobs, parsed }
+    }
+
+    pub fn parse(&mut self, key: K, value: String) {
+        self.parse_all(vec![(key, value)]);
+    }
+
+    // The values come back all together, and are dropped together if newer ones
+    // are requested before the worker gets to them. A dead worker gets replaced
+    // by a new one.
+    pub fn parse_all(&mut self, values: Vec<(K, String)>) {
+        if let Err(SendError(values)) = self.jobs.send(values) {
+            *self = Self::start();
+            // the new worker can't be gone already, it only ends when dropped or panicking
+            let _ = self.jobs.send(values);
+        }
+    }
+
+    // The values parsed since the last call, without blocking. Err once the worker
+    // died, the values it was reading will never come.
+    pub fn parsed_values(&self) -> Result<Vec<(K, ParsedValue)>, String> {
+        let mut values = Vec::new();
+        loop {
+            match self.parsed.try_recv() {
+                Ok(parsed) => values.extend(parsed),
+                Err(TryRecvError::Empty) => return Ok(values),
+                Err(TryRecvError::Disconnected) if values.is_empty() => {
+                    return Err("The value reader stopped".to_string())
+                }
+                Err(TryRecvError::Disconnected) => return Ok(values),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wait_for_values(parser: &ValueParser<usize>) -> Vec<(usize, ParsedValue)> {
+        for _ in 0..500 {
+            let values = parser.parsed_values().unwrap();
+            if !values.is_empty() {
+                return values;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("values never got parsed");
+    }
+
+    #[test]
+    fn dead_worker_test() {
+        // a parser whose worker is gone
+        let (jobs, _) = mpsc::channel();
+        let (_, parsed) = mpsc::channel();
+        let mut parser: ValueParser<usize> = ValueParser { jobs, parsed };
+        assert!(parser.parsed_values().is_err());
+
+        // asking for values starts a new one
+        parser.parse_all(vec![(1, "[1 2]".to_string()), (2, "{:a".to_string())]);
+        let values = wait_for_values(&parser);
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0].1.as_ref().as_ref().unwrap().to_string(), "[1 2]");
+        assert!(values[1].1.is_err());
+    }
+}
@@ -1,11 +1,21 @@
+use crate::chrome_trace;
+use crate::coverage;
+use crate::editor;
+use crate::flamegraph;
+use crate::flamegraph::FlameWeight;
 use crate::lisp_pprinter;
 use crate::lisp_pprinter::PrintToken;
 use crate::lisp_reader;
-use crate::lisp_reader::PrintableLispForm;
+use crate::lisp_reader::{PrintableLispForm, ReadError, Truncation};
+use crate::session;
 use crate::state::Form;
-use crate::state::{Coord, DebuggerState, DebuggerTool, ExecTrace, Flow, FlowThread, FlowTool};
-use crate::util_types::CallStackTreeNode;
-use egui::{Align, Color32, Label, Layout, RichText, Sense, TextStyle, Ui};
+use crate::state::{
+    Bookmark, CodeLayout, Coord, DebuggerState, DebuggerTool, ExecTrace, Flow, FlowThread,
+    FlowTool, HotCoordsMode, SourceLocation, ValueInspector,
+};
+use crate::syntax::SyntaxTheme;
+use crate::util_types::{CallStackTreeNode, TableState};
+use egui::{Align, Color32, Label, Layout, Response, RichText, Sense, TextStyle, Ui};
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::sync::mpsc::Sender;
@@ -14,6 +24,8 @@ use std::sync::{Arc, Mutex};
 pub struct DebuggerApp {
     state_arc: Arc<Mutex<DebuggerState>>,
     pub ctx_chan_sender: Sender<egui::CtxRef>,
+    session_file: String,
+    session_status: Option<String>,
 }
 
 impl DebuggerApp {
@@ -24,52 +36,160 @@ impl DebuggerApp {
         Self {
             state_arc,
             ctx_chan_sender,
+            session_file: "flowstorm-session.json".to_string(),
+            session_status: None,
         }
     }
 }
 
-fn hot_token_label(ui: &mut Ui, thread: &mut FlowThread, form: &Form, coord: &Coord, text: &str) {
-    let mut rich_text = RichText::new(text);
+const CTX_MENU_MAX_HITS: usize = 20;
+
+// Narrower than this forms just overflow, since laying them out gets pointless
+const MIN_CODE_WIDTH: usize = 20;
+
+// Like &s[0..max] but never splits a char
+fn short_str(s: &str, max: usize) -> &str {
+    match s.char_indices().nth(max) {
+        Some((i, _)) => &s[0..i],
+        None => s,
+    }
+}
+
+// Cold coords are blue, the hottest ones red
+fn heat_color(heat: f32) -> Color32 {
+    let lerp = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * heat) as u8;
+    Color32::from_rgb(lerp(90, 255), lerp(160, 40), lerp(255, 40))
+}
+
+// Where the editor can open a token, when there is an editor to open it with
+fn editor_location(form: &Form, coord: &Coord) -> Option<SourceLocation> {
+    editor::editor_launcher()?;
+    form.coord_location(coord).filter(|l| l.file.is_some())
+}
+
+fn open_in_editor_button(ui: &mut Ui, location: &SourceLocation) {
+    if ui
+        .button("Open in editor")
+        .on_hover_text(location.to_string())
+        .clicked()
+    {
+        open_in_editor(location);
+        ui.close_menu();
+    }
+}
+
+fn open_in_editor(location: &SourceLocation) {
+    if let Some(launcher) = editor::editor_launcher() {
+        if let Err(e) = editor::open_location(launcher, location) {
+            println!("WARNING! {}", e);
+        }
+    }
+}
+
+fn editor_context_menu(response: Response, location: &Option<SourceLocation>) -> Response {
+    match location {
+        Some(location) => response.context_menu(|ui| open_in_editor_button(ui, location)),
+        None => response,
+    }
+}
+
+// Hot coords colors go on top of the syntax ones of rich_text
+fn hot_token_label(
+    ui: &mut Ui,
+    thread: &mut FlowThread,
+    form: &Form,
+    coord: &Coord,
+    mut rich_text: RichText,
+) {
+    let location = editor_location(form, coord);
     if thread.is_coord_hot(form.form_id, coord) {
         rich_text = rich_text.color(Color32::YELLOW);
         let curr_executing = thread
             .execution
             .is_current_coord_executing(form.form_id, coord);
+
+        let (total_hits, first_trace_idx) = thread
+            .hot_coord(form.form_id, coord)
+            .map_or((0, 0), |hc| (hc.hits, hc.first_trace_idx));
+        let hits = thread.coord_hit_count(form.form_id, coord);
+
+        if thread.heatmap {
+            rich_text = rich_text.color(heat_color(thread.heat(hits)));
+        } else if hits > 1 {
+            rich_text = rich_text.color(Color32::from_rgb(245, 126, 7));
+        }
         if curr_executing {
             rich_text = rich_text.color(Color32::GREEN);
         }
+        let hits_text = format!("{} hits", hits);
 
-        let coord_traces = &thread.execution.traces_for_coord(form.form_id, coord);
-
-        if coord_traces.len() > 1 {
-            if !curr_executing {
-                rich_text = rich_text.color(Color32::from_rgb(245, 126, 7));
-            }
+        if total_hits > 1 {
             let label = Label::new(rich_text).sense(Sense::click());
+            // only built while the menu is open
             let label_ctx_menu = |ui: &mut Ui| {
-                for (trace_idx, t) in coord_traces {
-                    if ui.button(&t.result).clicked() {
-                        thread.jump_to(trace_idx);
+                if ui.button(format!("Show all {} hits", total_hits)).clicked() {
+                    thread.show_coord_hits(form.form_id, coord);
+                    ui.close_menu();
+                }
+                ui.separator();
+                let trace_idxs: Vec<usize> = thread
+                    .hot_coord(form.form_id, coord)
+                    .map(|hc| {
+                        hc.trace_idxs
+                            .iter()
+                            .take(CTX_MENU_MAX_HITS)
+                            .copied()
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                for trace_idx in trace_idxs {
+                    let result = match &thread.execution.traces[trace_idx] {
+                        ExecTrace::ExprTrace(et) => short_str(&et.result, 80),
+                        ExecTrace::FnCallTrace(_) => continue,
+                    };
+                    if ui.button(result).clicked() {
+                        thread.jump_to(&trace_idx);
                         ui.close_menu();
                     }
                 }
+                if let Some(location) = &location {
+                    ui.separator();
+                    open_in_editor_button(ui, location);
+                }
             };
 
-            if ui.add(label).context_menu(label_ctx_menu).clicked() {
-                let (idx, _) = coord_traces[0];
-                thread.jump_to(&idx);
-            }
-        } else {
             if ui
-                .add(Label::new(rich_text).sense(Sense::click()))
+                .add(label)
+                .on_hover_text(hits_text)
+                .context_menu(label_ctx_menu)
                 .clicked()
             {
-                if let Some(e) = coord_traces.iter().next() {
-                    let (trace_idx, _) = e;
-                    thread.jump_to(trace_idx);
-                }
+                thread.jump_to(&first_trace_idx);
+            }
+        } else {
+            let response = ui
+                .add(Label::new(rich_text).sense(Sense::click()))
+                .on_hover_text(hits_text);
+            if editor_context_menu(response, &location).clicked() {
+                thread.jump_to(&first_trace_idx);
             };
         }
+    } else if thread.is_coord_pending(form.form_id, coord) {
+        rich_text = rich_text.color(ui.visuals().weak_text_color());
+        let response = ui
+            .add(Label::new(rich_text).sense(Sense::click()))
+            .on_hover_text("Runs after the current step");
+        if editor_context_menu(response, &location).clicked() {
+            if let Some(trace_idx) = thread
+                .hot_coord(form.form_id, coord)
+                .map(|hc| hc.first_trace_idx)
+            {
+                thread.jump_to(&trace_idx);
+            }
+        }
+    } else if location.is_some() {
+        let response = ui.add(Label::new(rich_text).sense(Sense::click()));
+        editor_context_menu(response, &location);
     } else {
         ui.label(rich_text);
     }
@@ -79,6 +199,7 @@ fn flow_callstack_tree(
     ui: &mut Ui,
     flow_thread: &FlowThread,
     tree_pointer_mut: &Arc<Mutex<CallStackTreeNode>>,
+    show_fn_calls: &mut Option<(String, String)>,
 ) {
     let tree = tree_pointer_mut.lock().unwrap();
     let idx = tree.trace_idx;
@@ -92,7 +213,14 @@ fn flow_callstack_tree(
         let ch = egui::CollapsingHeader::new(fn_call_text).id_source(idx);
         ch.show(ui, |ui| {
             for child in &tree.childs {
-                flow_callstack_tree(ui, flow_thread, child);
+                flow_callstack_tree(ui, flow_thread, child, show_fn_calls);
+            }
+        })
+        .header_response
+        .context_menu(|ui| {
+            if ui.button("Calls of this function").clicked() {
+                *show_fn_calls = Some((fct.fn_ns.clone(), fct.fn_name.clone()));
+                ui.close_menu();
             }
         });
     } else {
@@ -101,10 +229,46 @@ fn flow_callstack_tree(
 }
 
 fn flow_call_stack_block(ui: &mut Ui, flow_thread: &mut FlowThread) {
+    let mut show_fn_calls = None;
     if let Some(cst) = &flow_thread.call_stack_tree {
         let root_pointer = Arc::clone(&cst.root);
-        flow_callstack_tree(ui, flow_thread, &root_pointer);
+        flow_callstack_tree(ui, flow_thread, &root_pointer, &mut show_fn_calls);
+    }
+    if let Some((fn_ns, fn_name)) = show_fn_calls {
+        flow_thread.show_fn_calls(&fn_ns, &fn_name);
+    }
+}
+
+// The body font isn't monospace, so this is roughly how many chars fit in width
+fn width_in_chars(ui: &Ui, width: f32) -> usize {
+    let char_width = ui.fonts().glyph_width(TextStyle::Body, 'x');
+    usize::max(MIN_CODE_WIDTH, (width / char_width) as usize)
+}
+
+// Chars of the line numbers gutter, including the space after the number
+const GUTTER_CHARS: usize = 5;
+
+fn end_code_row(ui: &mut Ui, row_height: f32) {
+    ui.allocate_exact_size(egui::vec2(0.0, row_height), Sense::hover()); // make sure we take up some height
+    ui.end_row();
+    ui.set_row_height(row_height);
+}
+
+// Right aligned in the gutter, blank when we don't know the line. Forms without a
+// gutter get nothing.
+fn line_number_label(ui: &mut Ui, gutter: &str, line: Option<usize>) {
+    if gutter.is_empty() {
+        return;
     }
+    let text = match line {
+        Some(line) => format!("{:>1$} ", line, gutter.len() - 1),
+        None => gutter.to_string(),
+    };
+    ui.label(
+        RichText::new(text)
+            .monospace()
+            .color(ui.visuals().weak_text_color()),
+    );
 }
 
 fn flow_code_block(ui: &mut Ui, forms: Vec<&Form>, flow_thread: &mut FlowThread) {
@@ -117,52 +281,107 @@ fn flow_code_block(ui: &mut Ui, forms: Vec<&Form>, flow_thread: &mut FlowThread)
         .with_main_wrap(true)
         .with_cross_align(Align::BOTTOM);
 
+    let char_width = ui.fonts().glyph_width(TextStyle::Body, 'x');
+    let width_chars = width_in_chars(ui, ui.available_width());
+
+    let theme = SyntaxTheme::for_visuals(ui.visuals());
+
     ui.allocate_ui_with_layout(initial_size, layout, |ui| {
         ui.spacing_mut().item_spacing.x = 0.0;
         let row_height = (*ui.fonts())[TextStyle::Body].row_height();
         ui.set_row_height(row_height);
 
         for form in forms.iter() {
-            if let ExecTrace::FnCallTrace(fct) = &flow_thread.execution.executing_trace() {
+            if let ExecTrace::FnCallTrace(fct) = flow_thread.execution.executing_trace().clone() {
                 if fct.form_id == form.form_id {
                     let fn_call_text = format!("({} {})", &fct.fn_name, &fct.args_vec);
                     let fn_call_text =
                         RichText::new(&fn_call_text[0..usize::min(80, fn_call_text.len())])
                             .color(Color32::GREEN);
-                    ui.label(fn_call_text);
-                    ui.allocate_exact_size(egui::vec2(0.0, row_height), Sense::hover()); // make sure we take up some height
-                    ui.end_row();
-                    ui.set_row_height(row_height);
+                    ui.label(fn_call_text).context_menu(|ui| {
+                        if ui.button("Calls of this function").clicked() {
+                            flow_thread.show_fn_calls(&fct.fn_ns, &fct.fn_name);
+                            ui.close_menu();
+                        }
+                    });
+                    end_code_row(ui, row_height);
                 }
             }
 
-            for t in &form.print_tokens {
+            if let Err(e) = &form.lisp_form {
+                read_error_badge(ui, e);
+                ui.label(&form.form_str);
+            }
+
+            // forms we know the file of get a file:line header and line numbers
+            let gutter = match &form.location {
+                Some(location) => {
+                    let header = Label::new(
+                        RichText::new(location.to_string())
+                            .small()
+                            .color(ui.visuals().weak_text_color()),
+                    );
+                    if editor::editor_launcher().is_some() && location.file.is_some() {
+                        if ui
+                            .add(header.sense(Sense::click()))
+                            .on_hover_text("Open in editor")
+                            .clicked()
+                        {
+                            open_in_editor(location);
+                        }
+                    } else {
+                        ui.add(header);
+                    }
+                    end_code_row(ui, row_height);
+                    " ".repeat(GUTTER_CHARS)
+                }
+                None => String::new(),
+            };
+            let gutter_width =
+                ui.fonts().glyph_width(TextStyle::Monospace, ' ') * gutter.len() as f32;
+            let code_width = usize::max(
+                MIN_CODE_WIDTH,
+                width_chars.saturating_sub((gutter_width / char_width).ceil() as usize),
+            );
+
+            let tokens = form.code_tokens(flow_thread.code_layout, code_width);
+            let code_lines = form.code_lines(flow_thread.code_layout, &tokens);
+            let mut lines = code_lines.iter().copied();
+            line_number_label(ui, &gutter, lines.next().flatten());
+
+            for t in tokens.iter() {
+                let syntax_text = |text: &str| match theme.token_color(t) {
+                    Some(color) => RichText::new(text).color(color),
+                    None => RichText::new(text),
+                };
                 match t {
                     PrintToken::String(s) => {
-                        ui.label(RichText::new(format!("\"{}\"", s)));
+                        ui.label(syntax_text(&format!("\"{}\"", s)));
                     }
                     PrintToken::Regexp(exp) => {
-                        ui.label(RichText::new(format!("#\"{}\"", exp)));
+                        ui.label(syntax_text(&format!("#\"{}\"", exp)));
                     }
                     PrintToken::BlockOpen { val, coord } => {
-                        hot_token_label(ui, flow_thread, form, coord, val);
+                        hot_token_label(ui, flow_thread, form, coord, syntax_text(val));
                     }
                     PrintToken::BlockClose { val, coord } => {
-                        hot_token_label(ui, flow_thread, form, coord, val);
+                        hot_token_label(ui, flow_thread, form, coord, syntax_text(val));
+                    }
+                    PrintToken::Atomic { val, coord, .. } => {
+                        hot_token_label(ui, flow_thread, form, coord, syntax_text(val));
                     }
-                    PrintToken::Atomic { val, coord } => {
-                        hot_token_label(ui, flow_thread, form, coord, val);
+                    PrintToken::Comment(c) => {
+                        ui.label(syntax_text(c));
+                    }
+                    PrintToken::Text(t) => {
+                        ui.label(RichText::new(t));
                     }
                     PrintToken::Space => {
                         ui.label(RichText::new(" "));
                     }
                     PrintToken::Newline => {
-                        ui.allocate_exact_size(egui::vec2(0.0, row_height), Sense::hover()); // make sure we take up some height
-                        ui.end_row();
-                        ui.set_row_height(row_height);
-                    }
-                    PrintToken::PrintTokensVec(_) => {
-                        panic!("all print tokens should be flatten at this stage")
+                        end_code_row(ui, row_height);
+                        line_number_label(ui, &gutter, lines.next().flatten());
                     }
                 }
             }
@@ -177,6 +396,28 @@ fn flow_code_block(ui: &mut Ui, forms: Vec<&Form>, flow_thread: &mut FlowThread)
 fn flow_code_panel(ui: &mut Ui, forms: Vec<&Form>, flow_thread: &mut FlowThread) {
     egui::TopBottomPanel::top("flow_control_panel").show_inside(ui, |ui| {
         ui.horizontal_wrapped(|ui| {
+            if ui
+                .add_enabled(
+                    flow_thread.nav_history.can_go_back(),
+                    egui::Button::new("<"),
+                )
+                .on_hover_text("Back (Alt+Left)")
+                .clicked()
+            {
+                flow_thread.nav_back();
+            }
+            if ui
+                .add_enabled(
+                    flow_thread.nav_history.can_go_forward(),
+                    egui::Button::new(">"),
+                )
+                .on_hover_text("Forward (Alt+Right)")
+                .clicked()
+            {
+                flow_thread.nav_forward();
+            }
+            ui.separator();
+
             if ui.button("Prev").clicked() {
                 flow_thread.step_back();
             }
@@ -189,6 +430,43 @@ fn flow_code_panel(ui: &mut Ui, forms: Vec<&Form>, flow_thread: &mut FlowThread)
             if ui.button("Next").clicked() {
                 flow_thread.step_next();
             }
+            ui.separator();
+
+            if ui.button("Bookmark").clicked() {
+                let trace_idx = flow_thread.execution.curr_trace_idx;
+                flow_thread.add_bookmark(Bookmark::new(
+                    trace_idx,
+                    format!("Step {}", trace_idx),
+                    String::new(),
+                ));
+            }
+            ui.separator();
+
+            ui.checkbox(&mut flow_thread.heatmap, "Heatmap")
+                .on_hover_text("Color code by how many times it executed");
+            ui.separator();
+
+            ui.radio_value(&mut flow_thread.code_layout, CodeLayout::Pretty, "Pretty");
+            ui.radio_value(&mut flow_thread.code_layout, CodeLayout::Source, "Source")
+                .on_hover_text("As written, for forms the tracer sent the source of");
+            ui.separator();
+
+            ui.label("Highlight");
+            ui.radio_value(
+                &mut flow_thread.hot_coords_mode,
+                HotCoordsMode::Cumulative,
+                "Everything executed",
+            );
+            ui.radio_value(
+                &mut flow_thread.hot_coords_mode,
+                HotCoordsMode::UpToCurrent,
+                "Executed so far",
+            );
+            ui.radio_value(
+                &mut flow_thread.hot_coords_mode,
+                HotCoordsMode::UpToCurrentAndAfter,
+                "Executed so far and pending",
+            );
         });
     });
     egui::CentralPanel::default().show_inside(ui, |ui| {
@@ -198,6 +476,319 @@ fn flow_code_panel(ui: &mut Ui, forms: Vec<&Form>, flow_thread: &mut FlowThread)
     });
 }
 
+fn table_controls(ui: &mut Ui, table: &mut TableState, total: usize) {
+    ui.horizontal_wrapped(|ui| {
+        ui.label("Filter");
+        if ui.text_edit_singleline(&mut table.filter).changed() {
+            table.page = 0;
+        }
+        ui.separator();
+
+        let page_count = TableState::page_count(total);
+        if ui.button("Prev page").clicked() && table.page > 0 {
+            table.page = usize::min(table.page, page_count) - 1;
+        }
+        ui.label(format!(
+            "Page {}/{} ({} rows)",
+            usize::min(table.page + 1, page_count),
+            page_count,
+            total
+        ));
+        if ui.button("Next page").clicked() && table.page + 1 < page_count {
+            table.page += 1;
+        }
+    });
+}
+
+fn table_header(ui: &mut Ui, table: &mut TableState, titles: &[&str]) {
+    for (col, title) in titles.iter().enumerate() {
+        let title = if table.sort_col != col {
+            title.to_string()
+        } else if table.sort_desc {
+            format!("{} v", title)
+        } else {
+            format!("{} ^", title)
+        };
+        if ui.button(title).clicked() {
+            table.sort_by(col);
+        }
+    }
+    ui.end_row();
+}
+
+fn flow_coord_hits_panel(ui: &mut Ui, flow_thread: &mut FlowThread) {
+    let (form_id, coord) = match &flow_thread.coord_hits_view {
+        Some(view) => (view.form_id, view.coord.clone()),
+        None => return,
+    };
+    flow_thread.update_coord_hits();
+    let curr_trace_idx = flow_thread.execution.curr_trace_idx;
+    let view = flow_thread.coord_hits_view.as_mut().unwrap();
+    let mut jump = None;
+
+    egui::TopBottomPanel::top("coord_hits_controls_panel").show_inside(ui, |ui| {
+        ui.label(format!("Form {} coord {:?}", form_id, coord));
+        table_controls(ui, &mut view.table, view.hits.len());
+    });
+    let (table, hits) = (&mut view.table, &view.hits);
+    egui::CentralPanel::default().show_inside(ui, |ui| {
+        egui::ScrollArea::both().show(ui, |ui| {
+            egui::Grid::new("coord_hits_grid")
+                .striped(true)
+                .show(ui, |ui| {
+                    table_header(ui, table, &["Idx", "Timestamp", "Fn args", "Result"]);
+                    for hit in &hits[table.page_range(hits.len())] {
+                        if ui
+                            .selectable_label(
+                                hit.trace_idx == curr_trace_idx,
+                                hit.trace_idx.to_string(),
+                            )
+                            .clicked()
+                        {
+                            jump = Some(hit.trace_idx);
+                        }
+                        ui.label(hit.timestamp.to_string());
+                        ui.label(short_str(hit.fn_args.as_deref().unwrap_or(""), 80));
+                        ui.label(short_str(&hit.result, 80));
+                        ui.end_row();
+                    }
+                });
+        });
+    });
+
+    if let Some(trace_idx) = jump {
+        flow_thread.jump_to(&trace_idx);
+    }
+    // the controls and header may have changed the filter or sort
+    if flow_thread.update_coord_hits() {
+        ui.ctx().request_repaint();
+    }
+}
+
+fn flow_fn_calls_panel(ui: &mut Ui, flow_thread: &mut FlowThread) {
+    let (fn_ns, fn_name) = match &flow_thread.fn_calls_view {
+        Some(view) => (view.fn_ns.clone(), view.fn_name.clone()),
+        None => return,
+    };
+    flow_thread.update_fn_calls();
+    let curr_trace_idx = flow_thread.execution.curr_trace_idx;
+    let view = flow_thread.fn_calls_view.as_mut().unwrap();
+    let mut jump = None;
+
+    egui::TopBottomPanel::top("fn_calls_controls_panel").show_inside(ui, |ui| {
+        ui.label(format!("Calls of {}/{}", fn_ns, fn_name));
+        table_controls(ui, &mut view.table, view.calls.len());
+    });
+    let (table, calls) = (&mut view.table, &view.calls);
+    egui::CentralPanel::default().show_inside(ui, |ui| {
+        egui::ScrollArea::both().show(ui, |ui| {
+            egui::Grid::new("fn_calls_grid")
+                .striped(true)
+                .show(ui, |ui| {
+                    table_header(ui, table, &["Idx", "Args", "Result", "Depth", "Duration"]);
+                    for call in &calls[table.page_range(calls.len())] {
+                        if ui
+                            .selectable_label(
+                                call.trace_idx == curr_trace_idx,
+                                call.trace_idx.to_string(),
+                            )
+                            .clicked()
+                        {
+                            jump = Some(call.trace_idx);
+                        }
+                        ui.label(short_str(&call.args_vec, 80));
+                        ui.label(short_str(call.result.as_deref().unwrap_or("-"), 80));
+                        ui.label(call.depth.to_string());
+                        ui.label(match call.duration {
+                            Some(d) => format!("{}ms", d),
+                            None => "-".to_string(),
+                        });
+                        ui.end_row();
+                    }
+                });
+        });
+    });
+
+    if let Some(trace_idx) = jump {
+        flow_thread.jump_to(&trace_idx);
+    }
+    // the controls and header may have changed the filter or sort
+    if flow_thread.update_fn_calls() {
+        ui.ctx().request_repaint();
+    }
+}
+
+fn flow_profiler_panel(ui: &mut Ui, flow_thread: &mut FlowThread) {
+    let view = &mut flow_thread.profiler_view;
+    let mut profile = view.profile.clone();
+    let whole_flow = &mut view.whole_flow;
+    let table = &mut view.table;
+    let mut show_fn_calls = None;
+
+    if !table.filter.is_empty() {
+        profile.retain(|p| format!("{}/{}", p.fn_ns, p.fn_name).contains(&table.filter));
+    }
+    match table.sort_col {
+        0 => profile.sort_by(|a, b| (&a.fn_ns, &a.fn_name).cmp(&(&b.fn_ns, &b.fn_name))),
+        1 => profile.sort_by_key(|p| p.calls),
+        3 => profile.sort_by_key(|p| p.self_time),
+        4 => profile.sort_by_key(|p| p.min_time),
+        5 => profile.sort_by_key(|p| p.avg_time()),
+        6 => profile.sort_by_key(|p| p.max_time),
+        7 => profile.sort_by_key(|p| p.max_recursion_depth),
+        _ => profile.sort_by_key(|p| p.total_time),
+    }
+    if table.sort_desc {
+        profile.reverse();
+    }
+
+    egui::TopBottomPanel::top("profiler_controls_panel").show_inside(ui, |ui| {
+        ui.horizontal_wrapped(|ui| {
+            ui.radio_value(whole_flow, false, "This thread");
+            ui.radio_value(whole_flow, true, "Whole flow");
+        });
+        table_controls(ui, table, profile.len());
+    });
+    egui::CentralPanel::default().show_inside(ui, |ui| {
+        egui::ScrollArea::both().show(ui, |ui| {
+            egui::Grid::new("profiler_grid")
+                .striped(true)
+                .show(ui, |ui| {
+                    table_header(
+                        ui,
+                        table,
+                        &[
+                            "Fn",
+                            "Calls",
+                            "Total",
+                            "Self",
+                            "Min",
+                            "Avg",
+                            "Max",
+                            "Max depth",
+                        ],
+                    );
+                    for p in &profile[table.page_range(profile.len())] {
+                        if ui
+                            .button(format!("{}/{}", p.fn_ns, p.fn_name))
+                            .on_hover_text("Show the calls of this function")
+                            .clicked()
+                        {
+                            show_fn_calls = Some((p.fn_ns.clone(), p.fn_name.clone()));
+                        }
+                        ui.label(p.calls.to_string());
+                        ui.label(format!("{}ms", p.total_time));
+                        ui.label(format!("{}ms", p.self_time));
+                        ui.label(format!("{}ms", p.min_time));
+                        ui.label(format!("{}ms", p.avg_time()));
+                        ui.label(format!("{}ms", p.max_time));
+                        ui.label(p.max_recursion_depth.to_string());
+                        ui.end_row();
+                    }
+                });
+        });
+    });
+
+    if let Some((fn_ns, fn_name)) = show_fn_calls {
+        flow_thread.show_fn_calls(&fn_ns, &fn_name);
+    }
+}
+
+fn flow_flamegraph_panel(ui: &mut Ui, flow_thread: &mut FlowThread) {
+    let view = &mut flow_thread.flamegraph_view;
+    let mut export = false;
+
+    egui::TopBottomPanel::top("flamegraph_controls_panel").show_inside(ui, |ui| {
+        ui.horizontal_wrapped(|ui| {
+            ui.radio_value(&mut view.weight, FlameWeight::Duration, "Duration");
+            ui.radio_value(&mut view.weight, FlameWeight::CallCount, "Call count");
+            ui.separator();
+            ui.text_edit_singleline(&mut view.export_file);
+            export = ui.button("Export folded stacks").clicked();
+            if let Some(status) = &view.export_status {
+                ui.label(status);
+            }
+        });
+    });
+
+    if export {
+        let folded = flamegraph::folded_stacks(flow_thread, flow_thread.flamegraph_view.weight);
+        let view = &mut flow_thread.flamegraph_view;
+        view.export_status = Some(match std::fs::write(&view.export_file, folded) {
+            Ok(()) => format!("Exported to {}", view.export_file),
+            Err(e) => format!("Couldn't write {}: {}", view.export_file, e),
+        });
+    }
+
+    flow_thread.update_flamegraph();
+    let rects = &flow_thread.flamegraph_view.rects;
+    let curr_trace_idx = flow_thread.execution.curr_trace_idx;
+    let mut jump = None;
+
+    egui::CentralPanel::default().show_inside(ui, |ui| {
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            let row_height = (*ui.fonts())[TextStyle::Body].row_height() + 4.0;
+            let max_depth = rects.iter().map(|r| r.depth).max().unwrap_or(0);
+            let (response, painter) = ui.allocate_painter(
+                egui::vec2(ui.available_width(), (max_depth + 1) as f32 * row_height),
+                Sense::click(),
+            );
+            let origin = response.rect.min;
+            let total_width = response.rect.width();
+            let text_color = ui.visuals().strong_text_color();
+
+            let mut hovered = None;
+            for r in rects {
+                let rect = egui::Rect::from_min_size(
+                    origin + egui::vec2(r.start * total_width, r.depth as f32 * row_height),
+                    egui::vec2(r.width * total_width, row_height),
+                )
+                .shrink(0.5);
+                if rect.width() < 1.0 {
+                    continue;
+                }
+
+                // warm colors, shifted by name so the same fn always gets the same color
+                let hash = r.label.bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+                let fill = if r.trace_idx == curr_trace_idx {
+                    Color32::GREEN
+                } else {
+                    Color32::from_rgb(205 + hash % 50, 80 + hash % 120, 40)
+                };
+                painter.rect_filled(rect, 2.0, fill);
+
+                let text_width = r.label.len() as f32 * row_height / 2.0;
+                if rect.width() > text_width {
+                    painter.sub_region(rect).text(
+                        rect.left_center() + egui::vec2(2.0, 0.0),
+                        egui::Align2::LEFT_CENTER,
+                        &r.label,
+                        TextStyle::Body,
+                        Color32::BLACK,
+                    );
+                }
+
+                if response.hover_pos().is_some_and(|pos| rect.contains(pos)) {
+                    hovered = Some(r);
+                }
+            }
+
+            if let Some(r) = hovered {
+                if response.clicked() {
+                    jump = Some(r.trace_idx);
+                }
+                response.on_hover_text(
+                    RichText::new(format!("{} [{}]", r.label, r.trace_idx)).color(text_color),
+                );
+            }
+        });
+    });
+
+    if let Some(trace_idx) = jump {
+        flow_thread.jump_to(&trace_idx);
+    }
+}
+
 fn flow_call_stack_panel(ui: &mut Ui, flow_thread: &mut FlowThread) {
     egui::CentralPanel::default().show_inside(ui, |ui| {
         egui::ScrollArea::vertical().show(ui, |ui| {
@@ -208,22 +799,10 @@ fn flow_call_stack_panel(ui: &mut Ui, flow_thread: &mut FlowThread) {
 
 fn seq_collapsing_header(ui: &mut Ui, form: &PrintableLispForm) {
     match form {
-        PrintableLispForm::List {
-            childs,
-            style: _,
-            coord,
-        }
-        | PrintableLispForm::Vector {
-            childs,
-            style: _,
-            coord,
-        }
-        | PrintableLispForm::Set {
-            childs,
-            style: _,
-            coord,
-        } => {
-            // since the form is unstyled is going to print linear
+        PrintableLispForm::List { childs, coord }
+        | PrintableLispForm::Vector { childs, coord }
+        | PrintableLispForm::Set { childs, coord } => {
+            // lisp_form_print_tokens prints it in one line
             let linear_print =
                 lisp_pprinter::print_tokens_to_str(&lisp_pprinter::lisp_form_print_tokens(form));
             let ch =
@@ -236,11 +815,11 @@ fn seq_collapsing_header(ui: &mut Ui, form: &PrintableLispForm) {
             });
         }
         PrintableLispForm::Tagged { tag, form, coord } => {
-            // since the form is unstyled is going to print linear
+            // lisp_form_print_tokens prints it in one line
             let tagged_body =
                 lisp_pprinter::print_tokens_to_str(&lisp_pprinter::lisp_form_print_tokens(form));
             let linear_print = format!(
-                "#{}{}",
+                "#{} {}",
                 tag,
                 &tagged_body[0..usize::min(80, tagged_body.len())]
             );
@@ -256,18 +835,56 @@ fn seq_collapsing_header(ui: &mut Ui, form: &PrintableLispForm) {
     }
 }
 
+fn read_error_badge(ui: &mut Ui, e: &ReadError) {
+    ui.label(
+        RichText::new(" read error ")
+            .color(Color32::WHITE)
+            .background_color(Color32::DARK_RED),
+    )
+    .on_hover_text(e.to_string());
+}
+
+fn truncation_label(ui: &mut Ui, truncation: Truncation) {
+    let hover = match truncation {
+        Truncation::Depth => "Nested too deep to inspect",
+        Truncation::Length => "Too many elements to inspect",
+        Truncation::Elided => "Elided by *print-length*",
+    };
+    let text = PrintableLispForm::Truncated(truncation).to_string();
+    ui.label(RichText::new(text).color(ui.visuals().weak_text_color()))
+        .on_hover_text(hover);
+}
+
 fn result_form_tree(ui: &mut Ui, form: &PrintableLispForm) {
     match form {
-        PrintableLispForm::Atomic(s, _) => {
-            ui.label(s);
+        PrintableLispForm::Atomic(atom, _) => {
+            ui.label(&atom.text);
         }
         PrintableLispForm::String(s) => {
             ui.label(format!("\"{}\"", s));
         }
+        PrintableLispForm::Truncated(t) => truncation_label(ui, *t),
+        PrintableLispForm::TruncatedString { prefix, omitted } => {
+            ui.label(format!("\"{}...\"", prefix))
+                .on_hover_text(format!("{} more chars", omitted));
+        }
+        PrintableLispForm::Regexp(_)
+        | PrintableLispForm::ReaderMacro { .. }
+        | PrintableLispForm::AnonFn { .. }
+        | PrintableLispForm::Meta { .. }
+        | PrintableLispForm::ReaderConditional { .. } => {
+            ui.label(form.to_string());
+        }
+        PrintableLispForm::NamespacedMap { ns, map } => {
+            ui.horizontal_wrapped(|ui| {
+                ui.label(format!("#:{}", ns));
+                result_form_tree(ui, map);
+            });
+        }
         PrintableLispForm::Map {
             keys,
             vals,
-            style: _,
+            truncated,
             coord,
         } => {
             // TODO: we can't print a map linear since the printer doesn't support it
@@ -283,24 +900,122 @@ fn result_form_tree(ui: &mut Ui, form: &PrintableLispForm) {
                             result_form_tree(ui, v);
                         });
                     }
+                    if let Some(t) = truncated {
+                        truncation_label(ui, *t);
+                    }
                 });
         }
         _ => seq_collapsing_header(ui, form),
     }
 }
 
-fn flow_result(ui: &mut Ui, flow_thread: &FlowThread) {
-    if let Some(form) = &flow_thread.value_inspector {
-        result_form_tree(ui, form);
+// A value laid out by the pretty printer, with syntax highlighting
+fn pretty_value_block(ui: &mut Ui, tokens: &[PrintToken]) {
+    let theme = SyntaxTheme::for_visuals(ui.visuals());
+    let layout = Layout::left_to_right()
+        .with_main_wrap(true)
+        .with_cross_align(Align::BOTTOM);
+
+    ui.with_layout(layout, |ui| {
+        ui.spacing_mut().item_spacing.x = 0.0;
+        let row_height = (*ui.fonts())[TextStyle::Body].row_height();
+        ui.set_row_height(row_height);
+
+        for t in tokens {
+            if let PrintToken::Newline = t {
+                end_code_row(ui, row_height);
+                continue;
+            }
+            let text = RichText::new(lisp_pprinter::print_token_to_str(t));
+            match theme.token_color(t) {
+                Some(color) => ui.label(text.color(color)),
+                None => ui.label(text),
+            };
+        }
+    });
+}
+
+fn flow_result(ui: &mut Ui, flow_thread: &mut FlowThread) {
+    if flow_thread.poll_value_parser() {
+        // keep repainting until the worker is done
+        ui.ctx().request_repaint();
+    }
+    ui.checkbox(&mut flow_thread.pretty_result, "Pretty")
+        .on_hover_text("Show the value pretty printed instead of as a tree");
+
+    // the scroll area gives us all the width we want, so fit the visible one
+    let width_chars = width_in_chars(ui, ui.clip_rect().width());
+    if flow_thread.pretty_result {
+        // values we couldn't read still show as below
+        if let Some(tokens) = flow_thread.result_print_tokens(width_chars) {
+            pretty_value_block(ui, &tokens);
+            return;
+        }
+    }
+
+    match &flow_thread.value_inspector {
+        ValueInspector::Ready(parsed) => match parsed.as_ref() {
+            Ok(form) => result_form_tree(ui, form),
+            Err(e) => {
+                read_error_badge(ui, e);
+                if let ExecTrace::ExprTrace(et) = flow_thread.execution.executing_trace() {
+                    ui.label(&et.result);
+                }
+            }
+        },
+        ValueInspector::Parsing(_) => {
+            ui.label(RichText::new("Reading value...").color(ui.visuals().weak_text_color()));
+        }
+        ValueInspector::Failed(e) => {
+            ui.label(
+                RichText::new(" read error ")
+                    .color(Color32::WHITE)
+                    .background_color(Color32::DARK_RED),
+            )
+            .on_hover_text(e);
+            if let ExecTrace::ExprTrace(et) = flow_thread.execution.executing_trace() {
+                ui.label(&et.result);
+            }
+        }
+        ValueInspector::Empty => (),
     }
 }
 
 fn flow_locals(ui: &mut Ui, flow_thread: &mut FlowThread) {
+    if flow_thread.poll_value_parser() {
+        ui.ctx().request_repaint();
+    }
+    ui.checkbox(&mut flow_thread.pretty_locals, "Pretty")
+        .on_hover_text("Show the values pretty printed");
+
+    // what is left next to the widest symbol
+    let symbs_width = flow_thread
+        .current_locals()
+        .iter()
+        .map(|(symb, _)| symb.chars().count() + 2)
+        .max()
+        .unwrap_or(0);
+    let width_chars = width_in_chars(ui, ui.available_width()).saturating_sub(symbs_width);
+    let locals = if flow_thread.pretty_locals {
+        flow_thread.current_locals_print_tokens(width_chars)
+    } else {
+        flow_thread
+            .current_locals()
+            .into_iter()
+            .map(|(symb, val)| (symb, val, None))
+            .collect()
+    };
+
     egui::Grid::new("locals").show(ui, |ui| {
         ui.set_min_height(ui.available_height() / 3.0);
-        for (symb, val) in flow_thread.current_locals() {
-            ui.label(symb);
-            ui.label(val);
+        for (symb, val, pretty_tokens) in locals.iter() {
+            ui.label(*symb);
+            match pretty_tokens {
+                Some(tokens) => pretty_value_block(ui, tokens),
+                None => {
+                    ui.label(*val);
+                }
+            }
             ui.end_row();
         }
     });
@@ -312,6 +1027,19 @@ fn flow_thread(
     forms: Vec<&Form>,
     selected_flow_thread: &mut FlowThread,
 ) {
+    let (nav_back, nav_forward) = {
+        let input = ui.input();
+        (
+            input.modifiers.alt && input.key_pressed(egui::Key::ArrowLeft),
+            input.modifiers.alt && input.key_pressed(egui::Key::ArrowRight),
+        )
+    };
+    if nav_back {
+        selected_flow_thread.nav_back();
+    } else if nav_forward {
+        selected_flow_thread.nav_forward();
+    }
+
     ui.group(|ui| {
         egui::SidePanel::right("results_and_locals_panel")
             .resizable(true)
@@ -356,6 +1084,40 @@ fn flow_thread(
                         .clicked()
                     {
                         selected_flow_thread.selected_flow_tool = FlowTool::CallStack;
+                    } else if ui
+                        .selectable_label(
+                            selected_flow_thread.selected_flow_tool == FlowTool::Profiler,
+                            "Profiler",
+                        )
+                        .clicked()
+                    {
+                        selected_flow_thread.selected_flow_tool = FlowTool::Profiler;
+                    } else if ui
+                        .selectable_label(
+                            selected_flow_thread.selected_flow_tool == FlowTool::Flamegraph,
+                            "Flamegraph",
+                        )
+                        .clicked()
+                    {
+                        selected_flow_thread.selected_flow_tool = FlowTool::Flamegraph;
+                    } else if selected_flow_thread.coord_hits_view.is_some()
+                        && ui
+                            .selectable_label(
+                                selected_flow_thread.selected_flow_tool == FlowTool::CoordHits,
+                                "Coord hits",
+                            )
+                            .clicked()
+                    {
+                        selected_flow_thread.selected_flow_tool = FlowTool::CoordHits;
+                    } else if selected_flow_thread.fn_calls_view.is_some()
+                        && ui
+                            .selectable_label(
+                                selected_flow_thread.selected_flow_tool == FlowTool::FnCalls,
+                                "Fn calls",
+                            )
+                            .clicked()
+                    {
+                        selected_flow_thread.selected_flow_tool = FlowTool::FnCalls;
                     }
                 });
             });
@@ -367,6 +1129,18 @@ fn flow_thread(
                 FlowTool::CallStack => {
                     flow_call_stack_panel(ui, selected_flow_thread);
                 }
+                FlowTool::CoordHits => {
+                    flow_coord_hits_panel(ui, selected_flow_thread);
+                }
+                FlowTool::FnCalls => {
+                    flow_fn_calls_panel(ui, selected_flow_thread);
+                }
+                FlowTool::Profiler => {
+                    flow_profiler_panel(ui, selected_flow_thread);
+                }
+                FlowTool::Flamegraph => {
+                    flow_flamegraph_panel(ui, selected_flow_thread);
+                }
             }
         });
     });
@@ -387,9 +1161,31 @@ fn flow_threads(ui: &mut Ui, ctx: &egui::CtxRef, selected_flow: &mut Flow) {
                         selected_flow.selected_thread_id = Some(thread_id);
                     }
                 }
+
+                ui.separator();
+                if ui
+                    .button("Export Chrome trace")
+                    .on_hover_text("Open it in Perfetto or chrome://tracing")
+                    .clicked()
+                {
+                    let file_path = format!("flow-{}.trace.json", selected_flow.flow_id);
+                    selected_flow.export_status = Some(
+                        match chrome_trace::export_flow_chrome_trace(selected_flow, &file_path) {
+                            Ok(()) => format!("Exported to {}", file_path),
+                            Err(e) => e,
+                        },
+                    );
+                }
+                if let Some(status) = &selected_flow.export_status {
+                    ui.label(status);
+                }
             });
         });
 
+        if selected_flow.threads[&selected_thread_id].selected_flow_tool == FlowTool::Profiler {
+            selected_flow.update_thread_profile(selected_thread_id);
+        }
+
         egui::CentralPanel::default().show_inside(ui, |ui| {
             // HACKY, this shouldn't be here, but you know, borrow checker
             let mut selected_thread_forms = Vec::new();
@@ -443,6 +1239,141 @@ fn flows_tool(ui: &mut Ui, ctx: &egui::CtxRef, state: &mut DebuggerState) {
     }
 }
 
+fn bookmarks_tool(ui: &mut Ui, state: &mut DebuggerState) {
+    let mut flow_ids = state.flows_ids();
+    flow_ids.sort_unstable();
+
+    let mut jump = None;
+
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        for flow_id in flow_ids {
+            let flow = state.flows.get_mut(&flow_id).unwrap();
+            let mut thread_ids = flow.thread_ids();
+            thread_ids.sort_unstable();
+
+            for thread_id in thread_ids {
+                let thread = flow.threads.get_mut(&thread_id).unwrap();
+                let mut remove_idx = None;
+
+                for (i, bookmark) in thread.bookmarks.iter_mut().enumerate() {
+                    ui.group(|ui| {
+                        ui.horizontal(|ui| {
+                            if ui
+                                .button(format!(
+                                    "Flow-{} thread-{} [{}]",
+                                    flow_id, thread_id, bookmark.trace_idx
+                                ))
+                                .clicked()
+                            {
+                                jump = Some((flow_id, thread_id, bookmark.trace_idx));
+                            }
+                            ui.text_edit_singleline(&mut bookmark.label);
+                            if ui.button("Remove").clicked() {
+                                remove_idx = Some(i);
+                            }
+                        });
+                        ui.text_edit_multiline(&mut bookmark.note);
+                    });
+                }
+
+                if let Some(i) = remove_idx {
+                    thread.remove_bookmark(i);
+                }
+            }
+        }
+    });
+
+    if let Some((flow_id, thread_id, trace_idx)) = jump {
+        if let Some(flow) = state.flows.get_mut(&flow_id) {
+            if let Some(thread) = flow.threads.get_mut(&thread_id) {
+                thread.jump_to(&trace_idx);
+                flow.selected_thread_id = Some(thread_id);
+            }
+        }
+        state.select_flow(flow_id);
+        state.selected_tool = DebuggerTool::Flows;
+    }
+}
+
+fn coverage_tool(ui: &mut Ui, state: &mut DebuggerState) {
+    let selected_flow = match state.selected_flow_mut() {
+        Some(flow) => flow,
+        None => {
+            ui.heading("No flows yet");
+            return;
+        }
+    };
+    selected_flow.update_coverage();
+    let cov = &selected_flow.coverage;
+    let mut export = false;
+    let (executed, total) = cov
+        .iter()
+        .fold((0, 0), |(e, t), nc| (e + nc.executed(), t + nc.total()));
+
+    ui.horizontal(|ui| {
+        ui.heading(format!(
+            "Flow-{} coverage {}/{} ({:.2}%)",
+            selected_flow.flow_id,
+            executed,
+            total,
+            coverage::percentage(executed, total)
+        ));
+        export = ui.button("Export coverage").clicked();
+        if let Some(status) = &selected_flow.export_status {
+            ui.label(status);
+        }
+    });
+    ui.separator();
+
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        for nc in cov {
+            egui::CollapsingHeader::new(format!(
+                "{}    {}/{} ({:.2}%)",
+                nc.ns,
+                nc.executed(),
+                nc.total(),
+                coverage::percentage(nc.executed(), nc.total())
+            ))
+            .id_source(&nc.ns)
+            .show(ui, |ui| {
+                egui::Grid::new(format!("coverage_grid_{}", nc.ns))
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for fc in &nc.forms {
+                            let form = selected_flow.forms.get(&fc.form_id).unwrap();
+                            ui.label(short_str(&form.form_str, 60))
+                                .on_hover_text(&form.form_str);
+                            ui.label(format!("{}/{}", fc.executed, fc.total));
+                            ui.label(format!(
+                                "{:.2}%",
+                                coverage::percentage(fc.executed, fc.total)
+                            ));
+                            ui.vertical(|ui| {
+                                for b in fc.branches.iter().filter(|b| !b.covered) {
+                                    ui.colored_label(
+                                        egui::Color32::RED,
+                                        format!("{} {} never taken {:?}", b.kind, b.label, b.coord),
+                                    );
+                                }
+                            });
+                            ui.end_row();
+                        }
+                    });
+            });
+        }
+    });
+
+    if export {
+        let file_prefix = format!("flow-{}-coverage", selected_flow.flow_id);
+        selected_flow.export_status = Some(
+            match coverage::export_flow_coverage(selected_flow, &file_prefix) {
+                Ok(msg) => msg,
+                Err(e) => e,
+            },
+        );
+    }
+}
+
 fn refs_tool(ui: &mut Ui, _state: &mut DebuggerState) {
     ui.heading("REFS");
 }
@@ -488,7 +1419,8 @@ impl epi::App for DebuggerApp {
     /// Put your widgets into a `SidePanel`, `TopPanel`, `CentralPanel`, `Window` or `Area`.
     fn update(&mut self, ctx: &egui::CtxRef, frame: &epi::Frame) {
         // This is not optimal since we are keeping the lock for the entire frame
-        let mut state = self.state_arc.lock().unwrap();
+        let state_arc = Arc::clone(&self.state_arc);
+        let mut state = state_arc.lock().unwrap();
 
         egui::CentralPanel::default().show(ctx, |ui| {
             egui::TopBottomPanel::top("tool_selection_panel").show_inside(ui, |ui| {
@@ -516,6 +1448,47 @@ impl epi::App for DebuggerApp {
                         .clicked()
                     {
                         state.selected_tool = DebuggerTool::Timeline;
+                    } else if ui
+                        .selectable_label(
+                            state.selected_tool == DebuggerTool::Bookmarks,
+                            "Bookmarks",
+                        )
+                        .clicked()
+                    {
+                        state.selected_tool = DebuggerTool::Bookmarks;
+                    } else if ui
+                        .selectable_label(state.selected_tool == DebuggerTool::Coverage, "Coverage")
+                        .clicked()
+                    {
+                        state.selected_tool = DebuggerTool::Coverage;
+                    }
+
+                    ui.separator();
+                    ui.text_edit_singleline(&mut self.session_file);
+                    if ui.button("Save session").clicked() {
+                        self.session_status =
+                            Some(match session::save_session(&state, &self.session_file) {
+                                Ok(()) => format!("Session saved to {}", self.session_file),
+                                Err(e) => e,
+                            });
+                    }
+                    if ui.button("Load session").clicked() {
+                        self.session_status = Some(
+                            match session::load_session(&mut state, &self.session_file) {
+                                Ok(warnings) if warnings.is_empty() => {
+                                    format!("Session loaded from {}", self.session_file)
+                                }
+                                Ok(warnings) => format!(
+                                    "Session loaded from {}. {}",
+                                    self.session_file,
+                                    warnings.join(". ")
+                                ),
+                                Err(e) => e,
+                            },
+                        );
+                    }
+                    if let Some(status) = &self.session_status {
+                        ui.label(status);
                     }
                 });
             });
@@ -525,6 +1498,8 @@ impl epi::App for DebuggerApp {
                 DebuggerTool::Refs => refs_tool(ui, &mut state),
                 DebuggerTool::Taps => taps_tool(ui, &mut state),
                 DebuggerTool::Timeline => timeline_tool(ui, &mut state),
+                DebuggerTool::Bookmarks => bookmarks_tool(ui, &mut state),
+                DebuggerTool::Coverage => coverage_tool(ui, &mut state),
             });
 
             egui::TopBottomPanel::bottom("bottom_panel").show_inside(ui, |ui| {
@@ -1,5 +1,6 @@
 use crate::state::DebuggerState;
 use crate::state::Form;
+use crate::state::SourceLocation;
 use crate::state::{BindTrace, ExprTrace, FnCallTrace};
 use json::JsonValue;
 use std::net::TcpListener;
@@ -13,7 +14,7 @@ fn print_state_change_code(s: String) {
     }
 }
 
-fn u16_from_json_value(obj: JsonValue) -> u16 {
+pub fn u16_from_json_value(obj: JsonValue) -> u16 {
     let res: f64 = if let JsonValue::Number(n) = obj {
         f64::from(n)
     } else {
@@ -22,7 +23,7 @@ fn u16_from_json_value(obj: JsonValue) -> u16 {
     res as u16
 }
 
-fn i64_from_json_value(obj: JsonValue) -> i64 {
+pub fn i64_from_json_value(obj: JsonValue) -> i64 {
     let res: f64 = if let JsonValue::Number(n) = obj {
         f64::from(n)
     } else {
@@ -31,7 +32,7 @@ fn i64_from_json_value(obj: JsonValue) -> i64 {
     res as i64
 }
 
-fn u64_from_json_value(obj: JsonValue) -> u64 {
+pub fn u64_from_json_value(obj: JsonValue) -> u64 {
     let res: f64 = if let JsonValue::Number(n) = obj {
         f64::from(n)
     } else {
@@ -40,7 +41,7 @@ fn u64_from_json_value(obj: JsonValue) -> u64 {
     return res as u64;
 }
 
-fn string_from_json_value(obj: JsonValue) -> String {
+pub fn string_from_json_value(obj: JsonValue) -> String {
     return match &obj {
         JsonValue::String(fs) => String::from(fs),
         JsonValue::Short(fs) => fs.to_string(),
@@ -48,26 +49,82 @@ fn string_from_json_value(obj: JsonValue) -> String {
     };
 }
 
-fn bool_from_json_value(obj: JsonValue) -> bool {
+pub fn bool_from_json_value(obj: JsonValue) -> bool {
     return match &obj {
         JsonValue::Boolean(b) => b.clone(),
         _ => false,
     };
 }
 
+pub fn coord_from_json_value(obj: &JsonValue) -> Vec<u16> {
+    if let JsonValue::Array(v) = obj {
+        v.iter()
+            .map(|c: &JsonValue| -> u16 { u16_from_json_value(c.clone()) })
+            .collect()
+    } else {
+        panic!("coor json value is not an array");
+    }
+}
+
+// Where the form is in its file, tracers that don't know send no line
+pub fn location_from_json_value(obj: &JsonValue) -> Option<SourceLocation> {
+    obj["line"].as_usize().map(|line| SourceLocation {
+        file: obj["file"].as_str().map(|f| f.to_string()),
+        line,
+        column: obj["column"].as_usize(),
+    })
+}
+
+pub fn err_from_json_value(obj: &JsonValue) -> Option<String> {
+    match obj {
+        JsonValue::Null => None,
+        JsonValue::String(_) | JsonValue::Short(_) => Some(string_from_json_value(obj.clone())),
+        _ => match obj["error/message"].as_str() {
+            Some(msg) => Some(msg.to_string()),
+            None => Some(obj.dump()),
+        },
+    }
+}
+
 fn process_form_init_trace(state_ref: &Arc<Mutex<DebuggerState>>, obj: &JsonValue) {
     let flow_id = i64_from_json_value(obj["flow-id"].clone());
     let form_id = i64_from_json_value(obj["form-id"].clone());
     let timestamp = u64_from_json_value(obj["timestamp"].clone());
     let form_str = string_from_json_value(obj["form"].clone());
     let form_ns = string_from_json_value(obj["ns"].clone());
+    // older tracers don't send the source
+    let source = obj["source"].as_str().map(|s| s.to_string());
+
+    let location = location_from_json_value(obj);
 
+    let with_source = match &source {
+        Some(source) => format!(".with_source(r#\"{}\"#.to_string())", source),
+        None => String::new(),
+    };
+    let with_location = match &location {
+        Some(location) => format!(
+            ".with_location(SourceLocation {{ file: {}, line: {}, column: {:?} }})",
+            match &location.file {
+                Some(file) => format!("Some(r#\"{}\"#.to_string())", file),
+                None => "None".to_string(),
+            },
+            location.line,
+            location.column
+        ),
+        None => String::new(),
+    };
     print_state_change_code(format!(
-        "state.add_flow_form({},{},Form::new({}, \"{}\".to_string(), r#\"{}\"#.to_string(), {}), {});",
-        flow_id, form_id, form_id, form_ns, &form_str, timestamp, timestamp
+        "state.add_flow_form({},{},Form::new({}, \"{}\".to_string(), r#\"{}\"#.to_string(), {}){}{}, {});",
+        flow_id, form_id, form_id, form_ns, &form_str, timestamp, with_source, with_location, timestamp
     ));
 
-    let form = Form::new(form_id, form_ns, form_str, timestamp);
+    let mut form = Form::new(form_id, form_ns, form_str, timestamp);
+    if let Some(source) = source {
+        form = form.with_source(source);
+    }
+    if let Some(location) = location {
+        form = form.with_location(location);
+    }
 
     let mut state = state_ref.lock().expect("Can't get the lock on state mutex");
 
@@ -78,26 +135,28 @@ fn process_form_init_trace(state_ref: &Arc<Mutex<DebuggerState>>, obj: &JsonValu
 fn process_form_add_trace(state_ref: &Arc<Mutex<DebuggerState>>, obj: &JsonValue) {
     let flow_id = i64_from_json_value(obj["flow-id"].clone());
     let form_id = i64_from_json_value(obj["form-id"].clone());
-    let coord: Vec<u16> = if let JsonValue::Array(v) = &obj["coor"] {
-        v.iter()
-            .map(|c: &JsonValue| -> u16 { u16_from_json_value(c.clone()) })
-            .collect()
+    let coord = coord_from_json_value(&obj["coor"]);
+    let thread_id = u16_from_json_value(obj["thread-id"].clone());
+    let err = err_from_json_value(&obj["err"]);
+    // expressions that threw don't have a result
+    let result = if obj["result"].is_null() {
+        String::new()
     } else {
-        panic!("coor json value is not an array");
+        string_from_json_value(obj["result"].clone())
     };
-    let thread_id = u16_from_json_value(obj["thread-id"].clone());
-    let result = string_from_json_value(obj["result"].clone());
     let is_outer_form = bool_from_json_value(obj["outer-form?"].clone());
     let timestamp = u64_from_json_value(obj["timestamp"].clone());
 
-    // TODO: handle the :err filed
-
+    let with_err = match &err {
+        Some(err) => format!(".with_err(Some(r#\"{}\"#.to_string()))", err),
+        None => String::new(),
+    };
     print_state_change_code(format!(
-        "state.add_exec_trace({},{},ExprTrace::new({},r#\"{}\"#.to_string(),vec!{:?}, {}, {}));",
-        flow_id, thread_id, form_id, &result, &coord, is_outer_form, timestamp
+        "state.add_exec_trace({},{},ExprTrace::new({},r#\"{}\"#.to_string(),vec!{:?}, {}, {}){});",
+        flow_id, thread_id, form_id, &result, &coord, is_outer_form, timestamp, with_err
     ));
 
-    let trace = ExprTrace::new(form_id, result, coord, is_outer_form, timestamp);
+    let trace = ExprTrace::new(form_id, result, coord, is_outer_form, timestamp).with_err(err);
 
     let mut state = state_ref.lock().expect("Can't get the lock on state mutex");
     state.add_exec_trace(flow_id, thread_id, trace);
@@ -125,13 +184,7 @@ fn process_fn_call_trace(state_ref: &Arc<Mutex<DebuggerState>>, obj: &JsonValue)
 fn process_form_add_bind_trace(state_ref: &Arc<Mutex<DebuggerState>>, obj: &JsonValue) {
     let flow_id = i64_from_json_value(obj["flow-id"].clone());
     let form_id = i64_from_json_value(obj["form-id"].clone());
-    let coord: Vec<u16> = if let JsonValue::Array(v) = &obj["coor"] {
-        v.iter()
-            .map(|c: &JsonValue| -> u16 { u16_from_json_value(c.clone()) })
-            .collect()
-    } else {
-        panic!("coor json value is not an array");
-    };
+    let coord = coord_from_json_value(&obj["coor"]);
 
     let thread_id = u16_from_json_value(obj["thread-id"].clone());
     let symbol = string_from_json_value(obj["symbol"].clone());